@@ -0,0 +1,56 @@
+//! Storage of disk cache entries, one JSON file per key.
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Marks entries still being written, which are not listed as keys
+pub const TMP_PREFIX : &str = ".tmp-";
+
+pub fn entry_path(dir : &str, key : &str) -> PathBuf {
+    Path::new(dir).join(key)
+}
+
+/// Written to a temporary file that is renamed over the entry, so a crash never leaves a truncated entry behind.
+/// Entries may hold tokens, so they are only readable by the owner.
+pub fn write_entry<T : Serialize>(dir : &str, key : &str, payload : &T) -> std::io::Result<()> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)?;
+
+    let tmp_path = entry_path(dir, &format!("{}{}.{}", TMP_PREFIX, key, std::process::id()));
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let written = options.open(&tmp_path).and_then(|mut file| {
+        file.write_all(serde_json::to_vec(payload)?.as_slice())?;
+        file.sync_all()
+    }).and_then(|_| std::fs::rename(&tmp_path, entry_path(dir, key)));
+
+    if written.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    written
+}
+
+/// Missing entries are misses, unreadable ones are logged and treated as misses too
+pub fn read_entry<T : DeserializeOwned>(dir : &str, key : &str) -> Option<T> {
+    let mut file = std::fs::File::open(entry_path(dir, key)).ok()?;
+    let mut buf = Vec::new();
+    let parsed = file.read_to_end(&mut buf)
+        .map_err(|err| err.to_string())
+        .and_then(|_| serde_json::from_slice(&buf).map_err(|err| err.to_string()));
+
+    match parsed {
+        Ok(val) => Some(val),
+        Err(err) => {
+            warn!(target:"client_common::cache", "Ignoring cache entry '{}': {}", key, err);
+            None
+        }
+    }
+}
@@ -0,0 +1,3 @@
+//! Plumbing shared by the Eloverblik and Energi Data Service clients.
+pub mod cache;
+pub mod retry;
@@ -0,0 +1,120 @@
+use std::future::Future;
+use std::time::Duration;
+use log::debug;
+use rand::Rng;
+use reqwest::{Request, Response};
+
+/// How failed requests are retried. Rate limits and transient transport errors are retried, everything else is returned right away.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total attempts including the first one, 1 disables retries
+    pub max_attempts : usize,
+    pub initial_backoff : Duration,
+    pub max_backoff : Duration
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60)
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Wait before the next attempt, never more than `max_backoff`. A server provided `retry_after` wins, otherwise the backoff doubles per attempt with jitter.
+    pub fn backoff(&self, attempt : usize, retry_after : Option<Duration>) -> Duration {
+        if let Some(val) = retry_after {
+            return val.min(self.max_backoff);
+        }
+
+        let exp = self.initial_backoff.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1) as u32)).min(self.max_backoff);
+        // Equal jitter, so clients retrying at the same time spread out while still backing off
+        let half = exp / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+    }
+
+    /// Sends `req` through `execute` until it goes through, fails for good or the attempts run out, and returns the last outcome unchecked.
+    /// A rate limit asking for a longer wait than `max_backoff` is returned right away rather than retried early.
+    /// Transport errors are only retried when `idempotent`, as the server may have applied the request before the error.
+    pub async fn send<F, Fut>(&self, req : Request, idempotent : bool, mut execute : F) -> reqwest::Result<Response>
+        where F : FnMut(Request) -> Fut,
+              Fut : Future<Output = reqwest::Result<Response>> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let attempt_req = match req.try_clone() {
+                Some(val) => val,
+                // Streaming bodies cannot be sent twice
+                None => return execute(req).await
+            };
+
+            let resp = execute(attempt_req).await;
+            let retry_after = match &resp {
+                Err(err) if idempotent && is_transient(err) => None,
+                Ok(val) if is_rate_limited(val) => retry_after(val).map(|until| Duration::from_secs((until - chrono::Utc::now().timestamp()).max(0) as u64)),
+                _ => return resp
+            };
+
+            if attempt >= self.max_attempts || retry_after.map(|val| val > self.max_backoff).unwrap_or(false) {
+                return resp;
+            }
+
+            let wait = self.backoff(attempt, retry_after);
+            debug!(target:"client_common::retry", "Attempt {} of {} failed, retrying in {:?}", attempt, self.max_attempts, wait);
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Both APIs answer 503 as well as 429 when a client goes over its quota
+pub fn is_rate_limited(resp : &Response) -> bool {
+    matches!(resp.status().as_u16(), 429 | 503)
+}
+
+/// Parses a `Retry-After` header given either as seconds or as an HTTP date, into the unix timestamp it refers to
+pub fn retry_after(resp : &Response) -> Option<i64> {
+    let val = resp.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+
+    if let Ok(secs) = val.trim().parse::<i64>() {
+        return Some(chrono::Utc::now().timestamp() + secs);
+    }
+
+    chrono::DateTime::parse_from_rfc2822(val.trim()).ok().map(|date| date.timestamp())
+}
+
+/// Timeouts and failed connections, which another attempt may get past
+pub fn is_transient(err : &reqwest::Error) -> bool {
+    err.is_timeout() || err.is_connect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_and_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(8)
+        };
+
+        let first = policy.backoff(1, None);
+        assert!(first >= Duration::from_millis(500) && first <= Duration::from_secs(1));
+        let fourth = policy.backoff(4, None);
+        assert!(fourth >= Duration::from_secs(4) && fourth <= Duration::from_secs(8));
+        assert!(policy.backoff(10, None) <= Duration::from_secs(8));
+        assert_eq!(policy.backoff(1, Some(Duration::from_secs(3))), Duration::from_secs(3));
+        assert_eq!(policy.backoff(1, Some(Duration::from_secs(30))), Duration::from_secs(8));
+    }
+}
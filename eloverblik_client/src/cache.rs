@@ -1,18 +1,69 @@
 use std::collections::{HashMap};
 use std::fmt::{Debug, Formatter};
-use std::io::{Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use base64::Engine;
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use client_common::cache as disk;
+use log::warn;
+use serde::{Deserialize, Serialize};
 
-pub trait Cache<T> {
+// Send and Sync so a client holding a cache can be shared between tasks
+pub trait Cache<T> : Send + Sync {
     fn put(&mut self, key : &str, val : T, expiration_time : Option<i64>);
     fn get(&self, key : &str) -> Option<T>;
     fn has_expired(&self, key : &str) -> bool;
+    fn remove(&mut self, key : &str);
+    fn clear(&mut self);
+    fn keys(&self) -> Vec<String>;
+    /// Lookups since the cache was created. A `get` that finds the entry is a hit, a `get` without one or a `has_expired` that is true is a miss.
+    fn stats(&self) -> CacheStats;
 }
 
 impl<T> Debug for dyn Cache<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        f.debug_struct("Cache")
+            .field("keys", &self.keys())
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits : u64,
+    pub misses : u64
+}
+
+impl CacheStats {
+    pub fn hit_ratio(&self) -> Option<f64> {
+        match self.hits + self.misses {
+            0 => None,
+            total => Some(self.hits as f64 / total as f64)
+        }
+    }
+}
+
+// Counted behind &self, as lookups do not take the cache mutably
+#[derive(Clone, Debug, Default)]
+struct Counters {
+    hits : Arc<AtomicU64>,
+    misses : Arc<AtomicU64>
+}
+
+impl Counters {
+    fn record(&self, hit : bool) {
+        if hit {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed)
+        }
     }
 }
 
@@ -20,10 +71,22 @@ impl<T> Debug for dyn Cache<T> {
 pub struct InMemoryCache<T> {
     store : HashMap<String, T>,
     expiration : HashMap<String, i64>,
-    default_expiration_time_in_secs : i64
+    default_expiration_time_in_secs : i64,
+    counters : Counters
 }
 
-impl<T : Clone> Cache<T> for InMemoryCache<T> {
+impl<T> InMemoryCache<T> {
+    pub fn new(default_expiration_time_in_secs : i64) -> Self {
+        Self {
+            store: HashMap::new(),
+            expiration: HashMap::new(),
+            default_expiration_time_in_secs,
+            counters: Counters::default()
+        }
+    }
+}
+
+impl<T : Clone + Send + Sync> Cache<T> for InMemoryCache<T> {
     fn put(&mut self, key: &str, val: T, expiration_time : Option<i64>) {
         match expiration_time {
             None => {
@@ -37,24 +100,49 @@ impl<T : Clone> Cache<T> for InMemoryCache<T> {
     }
 
     fn get(&self, key: &str) -> Option<T> {
-        return match self.store.get(key) {
-            None => None,
-            Some(val) => Some(val.clone())
-        }
+        let val = self.store.get(key).cloned();
+        self.counters.record(val.is_some());
+        val
     }
 
     fn has_expired(&self, key: &str) -> bool {
-        return match self.expiration.get(key) {
+        let expired = match self.expiration.get(key) {
             None => true,
-            Some(timestamp) => chrono::Utc::now().timestamp() > timestamp.clone()
+            Some(timestamp) => chrono::Utc::now().timestamp() > *timestamp
+        };
+        if expired {
+            self.counters.record(false);
         }
+        expired
+    }
+
+    fn remove(&mut self, key: &str) {
+        self.store.remove(key);
+        self.expiration.remove(key);
+    }
+
+    fn clear(&mut self) {
+        self.store.clear();
+        self.expiration.clear();
+    }
+
+    fn keys(&self) -> Vec<String> {
+        let mut keys : Vec<String> = self.store.keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.counters.stats()
     }
 }
 
+/// Keeps every entry as a file under `path` and counts hits and misses. Construct it with [`DiskCache::new`].
 #[derive(Clone, Debug)]
 pub struct DiskCache {
     pub path : String,
-    pub default_expiration_time_in_secs : i64
+    pub default_expiration_time_in_secs : i64,
+    counters : Counters
 }
 
 
@@ -63,7 +151,7 @@ struct DiskCacheStructure<T> {
     pub expires_in : i64,
     pub data : T
 }
-impl<'b, T : AsRef<[u8]> + for<'a> Deserialize<'a> + From<Vec<u8>>> Cache<T> for DiskCache {
+impl<T : AsRef<[u8]> + for<'a> Deserialize<'a> + From<Vec<u8>>> Cache<T> for DiskCache {
     fn put(&mut self, key: &str, val: T, expiration_time: Option<i64>) {
         let expr_time = match expiration_time {
             None => {
@@ -79,46 +167,136 @@ impl<'b, T : AsRef<[u8]> + for<'a> Deserialize<'a> + From<Vec<u8>>> Cache<T> for
             data: base64::engine::general_purpose::STANDARD.encode(val),
         };
 
-        let mut path_buf = std::path::PathBuf::new();
-        path_buf.push(&self.path);
-        std::fs::create_dir_all(path_buf.as_path()).unwrap();
-        path_buf.push(key);
-        let mut file = std::fs::File::create(path_buf.as_path()).unwrap();
-        file.write_all(serde_json::to_vec(&payload).unwrap().as_slice()).unwrap();
+        if let Err(err) = self.write_entry(key, &payload) {
+            warn!(target:"eloverblik_client::cache", "Could not write cache entry '{}': {}", key, err);
+        }
     }
 
     fn get(&self, key: &str) -> Option<T> {
-        let mut path_buf = std::path::PathBuf::new();
-        path_buf.push(&self.path);
-        path_buf.push(key);
-        let mut file = std::fs::File::open(path_buf.as_path());
-
-        return match file {
-            Ok(mut f) => {
-                let mut buf = Vec::new();
-                f.read_to_end(&mut buf).unwrap();
-                let obj : DiskCacheStructure<T> = serde_json::from_slice(&buf).unwrap();
-                let decoded = base64::engine::general_purpose::STANDARD.decode(obj.data).unwrap();
-                Some(decoded.into())
+        let val = self.read_entry::<String>(key).and_then(|obj| match base64::engine::general_purpose::STANDARD.decode(obj.data) {
+            Ok(decoded) => Some(decoded.into()),
+            Err(err) => {
+                warn!(target:"eloverblik_client::cache", "Ignoring cache entry '{}': {}", key, err);
+                None
             }
-            Err(_) => None
-        };
+        });
+        self.counters.record(val.is_some());
+        val
     }
 
     fn has_expired(&self, key: &str) -> bool {
-        let mut path_buf = std::path::PathBuf::new();
-        path_buf.push(&self.path);
-        path_buf.push(key);
-        let file = std::fs::File::open(path_buf.as_path());
-
-        return match file {
-            Ok(mut f) => {
-                let mut buf = Vec::new();
-                f.read_to_end(&mut buf).unwrap();
-                let obj : DiskCacheStructure<T> = serde_json::from_slice(&buf).unwrap();
-                chrono::Utc::now().timestamp() > obj.expires_in
+        let expired = match self.read_entry::<String>(key) {
+            None => true,
+            Some(obj) => chrono::Utc::now().timestamp() > obj.expires_in
+        };
+        if expired {
+            self.counters.record(false);
+        }
+        expired
+    }
+
+    fn remove(&mut self, key: &str) {
+        match std::fs::remove_file(self.entry_path(key)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                warn!(target:"eloverblik_client::cache", "Could not remove cache entry '{}': {}", key, err);
             }
-            Err(_) => true
+            _ => {}
+        }
+    }
+
+    fn clear(&mut self) {
+        for key in <Self as Cache<T>>::keys(self) {
+            <Self as Cache<T>>::remove(self, &key);
+        }
+    }
+
+    fn keys(&self) -> Vec<String> {
+        let entries = match std::fs::read_dir(&self.path) {
+            Err(_) => return Vec::new(),
+            Ok(val) => val
         };
+
+        let mut keys : Vec<String> = entries
+            .flatten()
+            .filter(|entry| entry.file_type().map(|val| val.is_file()).unwrap_or(false))
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| !name.starts_with(disk::TMP_PREFIX))
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.counters.stats()
+    }
+}
+
+impl DiskCache {
+    /// Entries go to the `path` directory, which is created on the first write
+    pub fn new(path : &str, default_expiration_time_in_secs : i64) -> Self {
+        Self {
+            path: path.to_owned(),
+            default_expiration_time_in_secs,
+            counters: Counters::default()
+        }
+    }
+
+    fn entry_path(&self, key : &str) -> std::path::PathBuf {
+        disk::entry_path(&self.path, key)
+    }
+
+    fn write_entry<T : Serialize>(&self, key : &str, payload : &DiskCacheStructure<T>) -> std::io::Result<()> {
+        disk::write_entry(&self.path, key, payload)
+    }
+
+    fn read_entry<T : for<'a> Deserialize<'a>>(&self, key : &str) -> Option<DiskCacheStructure<T>> {
+        disk::read_entry(&self.path, key)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::cstring::CString;
+
+    #[test]
+    fn disk_cache_introspection() {
+        let path = std::env::temp_dir().join(format!("eloverblik-cache-test-{}", std::process::id()));
+        let mut cache : Box<dyn Cache<CString>> = Box::new(DiskCache::new(&path.to_string_lossy(), 3600));
+
+        cache.put("a", "first".to_owned().into(), None);
+        cache.put("b", "second".to_owned().into(), None);
+        assert_eq!(cache.keys(), vec!["a".to_owned(), "b".to_owned()]);
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("missing").is_none());
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 1 });
+        assert!(format!("{:?}", cache).contains("hits: 1"));
+
+        cache.remove("a");
+        assert_eq!(cache.keys(), vec!["b".to_owned()]);
+        cache.clear();
+        assert!(cache.keys().is_empty());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn disk_cache_entries_are_private_and_corrupt_ones_are_misses() {
+        let path = std::env::temp_dir().join(format!("eloverblik-cache-write-test-{}", std::process::id()));
+        let mut cache : Box<dyn Cache<CString>> = Box::new(DiskCache::new(&path.to_string_lossy(), 3600));
+
+        cache.put("token", "secret".to_owned().into(), None);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(path.join("token")).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        assert_eq!(cache.keys(), vec!["token".to_owned()]);
+
+        std::fs::write(path.join("token"), "{\"expires_in\": 17").unwrap();
+        assert!(cache.has_expired("token"));
+        assert!(cache.get("token").is_none());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -1,19 +1,222 @@
+use std::fmt::{Display, Formatter};
 use thiserror::Error;
 
+/// Longest part of a response body kept in an error
+const BODY_EXCERPT_LEN : usize = 512;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Catch-all error type")]
     Any(Box<dyn std::error::Error + Send>),
     #[error("Request error")]
     HttpRequestError(Box<dyn std::error::Error + Send>),
-    #[error("HTTP request rate limited")]
-    ElOverblikRateLimited(i64), // i64 -> time till limit expires
+    #[error("{context} rate limited the request until {retry_after}")]
+    ElOverblikRateLimited {
+        context : RequestContext,
+        /// Unix timestamp the limit expires at
+        retry_after : i64
+    },
+    #[error("Invalid date range: {0}")]
+    InvalidDateRange(String),
+    #[error("Request to {context} failed: {source}")]
+    RequestFailed {
+        context : RequestContext,
+        #[source]
+        source : reqwest::Error
+    },
+    #[error("{context} responded with HTTP {status}: {body}")]
+    HttpStatus {
+        context : RequestContext,
+        status : u16,
+        body : String
+    },
+    #[error("Could not parse the response from {context}: {source}")]
+    InvalidResponse {
+        context : RequestContext,
+        body : String,
+        #[source]
+        source : serde_json::Error
+    },
+    #[error("Access token is not a valid header value")]
+    InvalidToken,
+    #[error("Refresh token expired at {0}, create a new one on eloverblik.dk")]
+    RefreshTokenExpired(chrono::DateTime<chrono::Utc>),
+    #[error("Invalid timeseries export: {0}")]
+    InvalidExport(String),
+    #[error("{0} is not available on the {1:?} API")]
+    UnsupportedApi(String, crate::Api),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+    /// Drops the URL a transport error prints, for requests that carry a credential in their path
+    pub(crate) fn without_url(self) -> Self {
+        match self {
+            Error::RequestFailed { context, source } => Error::RequestFailed { context, source: source.without_url() },
+            err => err
+        }
+    }
+}
+
 impl From<reqwest::Error> for Error {
     fn from(value: reqwest::Error) -> Self {
         Self::HttpRequestError(Box::new(value))
     }
 }
+
+/// The call an error happened in, so failures can be traced back to an endpoint and the metering points it was asked about
+#[derive(Clone, Debug, Default)]
+pub struct RequestContext {
+    pub endpoint : String,
+    pub metering_points : Vec<String>,
+    /// Whether the call can be sent again after a transport error, which may have happened after Eloverblik applied it
+    pub idempotent : bool
+}
+
+impl RequestContext {
+    pub fn new(endpoint : &str) -> Self {
+        Self {
+            endpoint: endpoint.to_owned(),
+            metering_points: Vec::new(),
+            idempotent: true
+        }
+    }
+
+    /// For calls that change something, which are only retried when rate limited
+    pub fn not_idempotent(mut self) -> Self {
+        self.idempotent = false;
+        self
+    }
+
+    pub fn with_metering_points(mut self, metering_points : &[String]) -> Self {
+        self.metering_points = metering_points.to_vec();
+        self
+    }
+}
+
+impl Display for RequestContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.endpoint)?;
+        if !self.metering_points.is_empty() {
+            write!(f, " (metering points {})", self.metering_points.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// A metering point Eloverblik refused or failed to answer for, reported inside an otherwise successful response
+#[derive(Error, Clone, Debug)]
+#[error("Eloverblik returned {code} for metering point {id}: {text}")]
+pub struct ApiError {
+    pub id : String,
+    pub code : ApiErrorCode,
+    pub text : String
+}
+
+/// A chunk of a longer range that Eloverblik reported an error for, while the other chunks of the metering point were fetched
+#[derive(Error, Clone, Debug)]
+#[error("{error} ({start} to {end})")]
+pub struct ChunkError {
+    pub start : chrono::NaiveDate,
+    pub end : chrono::NaiveDate,
+    #[source]
+    pub error : ApiError
+}
+
+/// Error codes documented by Eloverblik, anything else is kept as [`ApiErrorCode::Other`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApiErrorCode {
+    NoError,
+    WrongNumberOfArguments,
+    InvalidMeteringPointId,
+    FromDateIsGreaterThanToday,
+    FromDateIsGreaterThanToDate,
+    ToDateCantBeEqualToFromDate,
+    ToDateIsGreaterThanToday,
+    InvalidDateFormat,
+    InvalidRequestParameters,
+    AccessToMeteringPointDenied,
+    NoMeteringPointDataAvailable,
+    InternalServerError,
+    Other(i64)
+}
+
+impl ApiErrorCode {
+    pub fn code(&self) -> i64 {
+        match self {
+            ApiErrorCode::NoError => 10000,
+            ApiErrorCode::WrongNumberOfArguments => 10001,
+            ApiErrorCode::InvalidMeteringPointId => 10003,
+            ApiErrorCode::FromDateIsGreaterThanToday => 30000,
+            ApiErrorCode::FromDateIsGreaterThanToDate => 30001,
+            ApiErrorCode::ToDateCantBeEqualToFromDate => 30002,
+            ApiErrorCode::ToDateIsGreaterThanToday => 30003,
+            ApiErrorCode::InvalidDateFormat => 30004,
+            ApiErrorCode::InvalidRequestParameters => 30005,
+            ApiErrorCode::AccessToMeteringPointDenied => 30006,
+            ApiErrorCode::NoMeteringPointDataAvailable => 30007,
+            ApiErrorCode::InternalServerError => 50000,
+            ApiErrorCode::Other(val) => *val
+        }
+    }
+}
+
+impl From<i64> for ApiErrorCode {
+    fn from(value: i64) -> Self {
+        match value {
+            10000 => ApiErrorCode::NoError,
+            10001 => ApiErrorCode::WrongNumberOfArguments,
+            10003 => ApiErrorCode::InvalidMeteringPointId,
+            30000 => ApiErrorCode::FromDateIsGreaterThanToday,
+            30001 => ApiErrorCode::FromDateIsGreaterThanToDate,
+            30002 => ApiErrorCode::ToDateCantBeEqualToFromDate,
+            30003 => ApiErrorCode::ToDateIsGreaterThanToday,
+            30004 => ApiErrorCode::InvalidDateFormat,
+            30005 => ApiErrorCode::InvalidRequestParameters,
+            30006 => ApiErrorCode::AccessToMeteringPointDenied,
+            30007 => ApiErrorCode::NoMeteringPointDataAvailable,
+            50000 => ApiErrorCode::InternalServerError,
+            val => ApiErrorCode::Other(val)
+        }
+    }
+}
+
+impl Display for ApiErrorCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiErrorCode::Other(val) => write!(f, "error code {}", val),
+            val => write!(f, "{:?} ({})", val, val.code())
+        }
+    }
+}
+
+/// Start of a response body, cut on a character boundary
+pub fn body_excerpt(body : &str) -> String {
+    match body.char_indices().nth(BODY_EXCERPT_LEN) {
+        None => body.to_owned(),
+        Some((idx, _)) => format!("{}...", &body[..idx])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_carry_context() {
+        let context = RequestContext::new("/api/meterdata/gettimeseries").with_metering_points(&["571313100000000001".to_owned()]);
+        let err = Error::HttpStatus {
+            context,
+            status: 400,
+            body: body_excerpt(&"x".repeat(1000))
+        };
+
+        let message = err.to_string();
+        assert!(message.starts_with("/api/meterdata/gettimeseries (metering points 571313100000000001) responded with HTTP 400: "));
+        assert_eq!(message.matches('x').count(), BODY_EXCERPT_LEN);
+
+        let err = Error::ElOverblikRateLimited { context: RequestContext::new("/api/token"), retry_after: 1690000000 };
+        assert_eq!(err.to_string(), "/api/token rate limited the request until 1690000000");
+    }
+}
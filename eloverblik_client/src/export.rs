@@ -0,0 +1,252 @@
+//! Parser for the CSV Eloverblik exports timeseries as, both from [`crate::Client::export_timeseries`] and from the web portal.
+use std::collections::HashMap;
+use chrono::{DateTime, Duration, LocalResult, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Europe::Copenhagen;
+use crate::error::{Error, Result};
+use crate::model::response::{MRid, MarketEvaluationPoint, MyEnergyDataMarketDocument, Period, Point, TimeInterval, TimeSeries};
+
+const METERING_POINT_COLUMN : &str = "Målepunkt id";
+const FROM_COLUMN : &str = "Fra dato";
+const TO_COLUMN : &str = "Til dato";
+const QUANTITY_COLUMN : &str = "Mængde";
+const UNIT_COLUMN : &str = "Måleenhed";
+const QUALITY_COLUMN : &str = "Kvalitet";
+/// Times in the export are Danish local time
+const DATE_FORMAT : &str = "%d-%m-%Y %H:%M:%S";
+
+struct Row {
+    metering_point_id : String,
+    start : DateTime<Utc>,
+    end : DateTime<Utc>,
+    quantity : String,
+    unit : String,
+    quality : String
+}
+
+/// Parses an export into a document holding a time series per metering point.
+/// Like the JSON API, hourly and quarterly points are split into a period per Danish day.
+pub fn parse_timeseries_csv(input : &str) -> Result<MyEnergyDataMarketDocument> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b';')
+        .trim(csv::Trim::All)
+        .from_reader(input.trim_start_matches('\u{feff}').as_bytes());
+
+    let headers = reader.headers().map_err(|err| Error::InvalidExport(err.to_string()))?.clone();
+    let column = |name : &str| headers.iter().position(|val| val.eq_ignore_ascii_case(name)).ok_or_else(|| Error::InvalidExport(format!("missing column '{}'", name)));
+    let (id_idx, from_idx, to_idx, quantity_idx, unit_idx, quality_idx) = (column(METERING_POINT_COLUMN)?, column(FROM_COLUMN)?, column(TO_COLUMN)?, column(QUANTITY_COLUMN)?, column(UNIT_COLUMN)?, column(QUALITY_COLUMN)?);
+
+    // The hour repeated when summer time ends is ambiguous, so rows are resolved against the previous end of the same point
+    let mut last_end : HashMap<String, DateTime<Utc>> = HashMap::new();
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|err| Error::InvalidExport(err.to_string()))?;
+        let line = record.position().map(|pos| pos.line()).unwrap_or_default();
+        let field = |idx : usize| record.get(idx).unwrap_or_default();
+
+        let metering_point_id = field(id_idx).to_owned();
+        let start = parse_local_time(field(from_idx), last_end.get(&metering_point_id).copied())
+            .ok_or_else(|| Error::InvalidExport(format!("line {}: invalid start '{}'", line, field(from_idx))))?;
+        let end = parse_local_time(field(to_idx), Some(start + Duration::minutes(1)))
+            .ok_or_else(|| Error::InvalidExport(format!("line {}: invalid end '{}'", line, field(to_idx))))?;
+        let quantity = field(quantity_idx).replace(',', ".");
+        if quantity.parse::<f64>().is_err() {
+            return Err(Error::InvalidExport(format!("line {}: invalid quantity '{}'", line, field(quantity_idx))));
+        }
+
+        last_end.insert(metering_point_id.clone(), end);
+        rows.push(Row {
+            metering_point_id,
+            start,
+            end,
+            quantity,
+            unit: field(unit_idx).to_uppercase(),
+            quality: quality_code(field(quality_idx)).to_owned()
+        });
+    }
+
+    let mut payload = MyEnergyDataMarketDocument::default();
+    for row in rows {
+        let resolution = resolution(row.start, row.end)
+            .ok_or_else(|| Error::InvalidExport(format!("unsupported interval {} to {}", row.start, row.end)))?;
+
+        let ts = match payload.time_series.iter().position(|val| val.market_evaluation_point.m_rid.name == row.metering_point_id) {
+            Some(idx) => &mut payload.time_series[idx],
+            None => {
+                payload.time_series.push(TimeSeries {
+                    m_rid: row.metering_point_id.clone(),
+                    business_type: String::new(),
+                    curve_type: "A01".to_owned(),
+                    measurement_unit_name: row.unit.clone(),
+                    market_evaluation_point: MarketEvaluationPoint {
+                        m_rid: MRid {
+                            coding_scheme: "A10".to_owned(),
+                            name: row.metering_point_id.clone()
+                        }
+                    },
+                    period: Vec::new()
+                });
+                payload.time_series.last_mut().unwrap()
+            }
+        };
+
+        // Hourly and quarterly periods span the whole Danish day like the API's, so a point's position says which hour or quarter it is
+        // even when the export starts mid-day or skips some
+        let day = splits_daily(resolution).then(|| danish_day(row.start)).flatten();
+        let continues = ts.period.last().map(|period| {
+            period.resolution == resolution && match day {
+                Some((day_start, _)) => period.time_interval.start == format_time(day_start),
+                None => period.time_interval.end == format_time(row.start)
+            }
+        }).unwrap_or(false);
+
+        if !continues {
+            let (start, end) = day.unwrap_or((row.start, row.start));
+            ts.period.push(Period {
+                resolution: resolution.to_owned(),
+                time_interval: TimeInterval {
+                    start: format_time(start),
+                    end: format_time(end)
+                },
+                point: Vec::new()
+            });
+        }
+
+        let period = ts.period.last_mut().unwrap();
+        let position = match day {
+            Some((day_start, _)) => (row.start - day_start).num_minutes() / (row.end - row.start).num_minutes() + 1,
+            None => {
+                period.time_interval.end = format_time(row.end);
+                period.point.len() as i64 + 1
+            }
+        };
+        period.point.push(Point {
+            position: position.to_string(),
+            out_quantity_quantity: row.quantity,
+            out_quantity_quality: row.quality
+        });
+
+        let (start, end) = (format_time(row.start), format_time(row.end));
+        if payload.period_time_interval.start.is_empty() || start < payload.period_time_interval.start {
+            payload.period_time_interval.start = start;
+        }
+        if end > payload.period_time_interval.end {
+            payload.period_time_interval.end = end;
+        }
+    }
+
+    Ok(payload)
+}
+
+// Picks the later of two ambiguous times when the earlier one would overlap what came before
+fn parse_local_time(val : &str, not_before : Option<DateTime<Utc>>) -> Option<DateTime<Utc>> {
+    let naive = NaiveDateTime::parse_from_str(val, DATE_FORMAT).ok()?;
+    match Copenhagen.from_local_datetime(&naive) {
+        LocalResult::Single(time) => Some(time.with_timezone(&Utc)),
+        LocalResult::Ambiguous(earliest, latest) => {
+            let earliest = earliest.with_timezone(&Utc);
+            match not_before {
+                Some(min) if earliest < min => Some(latest.with_timezone(&Utc)),
+                _ => Some(earliest)
+            }
+        }
+        LocalResult::None => None
+    }
+}
+
+fn resolution(start : DateTime<Utc>, end : DateTime<Utc>) -> Option<&'static str> {
+    let hours = (end - start).num_hours();
+    match (end - start).num_minutes() {
+        15 => Some("PT15M"),
+        60 => Some("PT1H"),
+        // Days are 23 to 25 hours and months 28 to 31 days, shifted an hour by summer time
+        _ if (23..=25).contains(&hours) => Some("P1D"),
+        _ if (671..=745).contains(&hours) => Some("P1M"),
+        _ if (8759..=8785).contains(&hours) => Some("P1Y"),
+        _ => None
+    }
+}
+
+fn splits_daily(resolution : &str) -> bool {
+    matches!(resolution, "PT15M" | "PT1H")
+}
+
+// Start and end of the Danish day a time falls in, which are 23 or 25 hours apart when summer time starts or ends
+fn danish_day(time : DateTime<Utc>) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let day = time.with_timezone(&Copenhagen).date_naive();
+    let midnight = |date : chrono::NaiveDate| Copenhagen.from_local_datetime(&date.and_hms_opt(0, 0, 0)?).earliest().map(|val| val.with_timezone(&Utc));
+    Some((midnight(day)?, midnight(day.succ_opt()?)?))
+}
+
+fn format_time(time : DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+// The export spells out the quality the JSON API gives as a code
+fn quality_code(val : &str) -> &str {
+    match val.to_lowercase().as_str() {
+        "målt" => "A04",
+        "estimeret" | "beregnet" => "A03",
+        "korrigeret" | "justeret" => "A01",
+        "mangler" | "ikke tilgængelig" => "A02",
+        _ => val
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_export_into_daily_periods() {
+        let input = "\u{feff}Målepunkt id;Fra dato;Til dato;Mængde;Måleenhed;Kvalitet;Type\n\
+            571313100000000000;29-10-2023 01:00:00;29-10-2023 02:00:00;0,5;KWH;Målt;Tidsserie\n\
+            571313100000000000;29-10-2023 02:00:00;29-10-2023 02:00:00;0,25;KWH;Målt;Tidsserie\n\
+            571313100000000000;29-10-2023 02:00:00;29-10-2023 03:00:00;0,125;KWH;Estimeret;Tidsserie\n\
+            571313100000000000;30-10-2023 00:00:00;30-10-2023 01:00:00;1;KWH;Målt;Tidsserie\n";
+
+        let doc = parse_timeseries_csv(input).unwrap();
+        let ts = &doc.time_series[0];
+        assert_eq!(ts.market_evaluation_point.m_rid.name, "571313100000000000");
+        assert_eq!(ts.period.len(), 2);
+
+        // The repeated hour at the end of summer time becomes two consecutive points
+        let first = &ts.period[0];
+        let starts : Vec<DateTime<Utc>> = first.point.iter().filter_map(|point| first.point_start(point)).collect();
+        assert_eq!(starts, vec![
+            "2023-10-28T23:00:00Z".parse::<DateTime<Utc>>().unwrap(),
+            "2023-10-29T00:00:00Z".parse().unwrap(),
+            "2023-10-29T01:00:00Z".parse().unwrap(),
+        ]);
+        // The period covers the whole 25 hour day, like the ones the API returns
+        assert_eq!(first.time_interval.start, "2023-10-28T22:00:00Z");
+        assert_eq!(first.time_interval.end, "2023-10-29T23:00:00Z");
+        assert_eq!(first.point[2].out_quantity_quantity, "0.125");
+        assert_eq!(first.point[2].out_quantity_quality, "A03");
+        assert_eq!(ts.period[1].time_interval.start, "2023-10-29T23:00:00Z");
+        assert_eq!(doc.period_time_interval.end, "2023-10-30T00:00:00Z");
+
+        assert!(matches!(parse_timeseries_csv("Målepunkt id;Fra dato\n"), Err(Error::InvalidExport(_))));
+    }
+
+    #[test]
+    fn positions_count_from_the_start_of_the_danish_day() {
+        // Starts mid-day and skips 12:00, on a summer day that starts at 22:00 UTC
+        let input = "Målepunkt id;Fra dato;Til dato;Mængde;Måleenhed;Kvalitet\n\
+            571313100000000000;02-08-2023 10:00:00;02-08-2023 11:00:00;1;KWH;Målt\n\
+            571313100000000000;02-08-2023 11:00:00;02-08-2023 12:00:00;2;KWH;Målt\n\
+            571313100000000000;02-08-2023 13:00:00;02-08-2023 14:00:00;4;KWH;Målt\n";
+
+        let doc = parse_timeseries_csv(input).unwrap();
+        let ts = &doc.time_series[0];
+        assert_eq!(ts.period.len(), 1);
+        let period = &ts.period[0];
+        assert_eq!(period.time_interval.start, "2023-08-01T22:00:00Z");
+        assert_eq!(period.time_interval.end, "2023-08-02T22:00:00Z");
+
+        let positions : Vec<&str> = period.point.iter().map(|point| point.position.as_str()).collect();
+        assert_eq!(positions, vec!["11", "12", "14"]);
+        assert_eq!(period.point_start(&period.point[2]), Some("2023-08-02T11:00:00Z".parse().unwrap()));
+        assert_eq!(doc.period_time_interval.start, "2023-08-02T08:00:00Z");
+        assert_eq!(doc.period_time_interval.end, "2023-08-02T12:00:00Z");
+    }
+}
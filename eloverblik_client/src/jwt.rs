@@ -0,0 +1,27 @@
+use base64::Engine;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Claims {
+    exp : Option<i64>
+}
+
+/// The `exp` claim of a JWT as a unix timestamp. The signature is not checked, this is only used to know when to get a new token.
+pub fn expiry(token : &str) -> Option<i64> {
+    let payload = token.split('.').nth(1)?;
+    let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+    let claims : Claims = serde_json::from_slice(&decoded).ok()?;
+    claims.exp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_exp_claim() {
+        let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(r#"{"sub":"test","exp":1700000000}"#);
+        assert_eq!(expiry(&format!("eyJhbGciOiJub25lIn0.{}.", payload)), Some(1700000000));
+        assert_eq!(expiry("not-a-jwt"), None);
+    }
+}
@@ -2,153 +2,543 @@ pub mod error;
 pub mod model;
 pub mod cache;
 pub mod types;
+pub mod ratelimit;
+pub use client_common::retry;
+pub mod jwt;
+pub mod export;
+#[cfg(feature = "test-support")]
+pub mod test_support;
 
 use std::sync::Arc;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
 use crossbeam::sync::ShardedLock;
-use log::debug;
-use reqwest::{Request, Response};
+use log::{debug, error, warn};
+use reqwest::header::HeaderValue;
+use reqwest::{Request, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use error::{Result, Error};
-use crate::cache::{Cache};
-use crate::error::Error::ElOverblikRateLimited;
-use crate::model::request::{GetMeteringDataTimeSeriesRequest, GetMeteringPointChargesRequest};
-use crate::model::response::{GetMeteringDataTimeSeriesResponse, GetMeteringPointChargesResponse, GetMeteringPointsResponse};
+use error::{body_excerpt, ChunkError, Error, RequestContext, Result};
+use crate::cache::{Cache, CacheStats};
+use crate::model::request::{AddRelationRequest, Aggregation, AuthorizationScope, ExportTimeSeriesRequest, GetMeterReadingsRequest, GetMeteringDataTimeSeriesRequest, GetMeteringPointChargesRequest, GetMeteringPointDetailsRequest, MeteringPoints};
+use crate::model::response::{AddRelationByAccessCodeResponse, AddRelationResponse, Authorization, DeleteRelationResponse, GetAuthorizationsResponse, GetMeterReadingsResponse, GetMeteringDataTimeSeriesResponse, GetMeteringPointChargesResponse, GetMeteringPointDetailsResponse, GetMeteringPointsResponse};
+use crate::ratelimit::{RateLimiter, RateLimits};
+use crate::retry::RetryPolicy;
 use crate::types::cstring::CString;
+use crate::types::secret::Secret;
+
+const BASE_URL : &str = "https://api.eloverblik.dk/customerapi";
+const THIRD_PARTY_BASE_URL : &str = "https://api.eloverblik.dk/thirdpartyapi";
+// Tokens of the two APIs are scoped differently, so they are cached apart
+const CACHE_KEY : &str = "ACCESS_TOKEN";
+const THIRD_PARTY_CACHE_KEY : &str = "THIRD_PARTY_ACCESS_TOKEN";
+/// Access tokens are replaced this long before they expire, so a request is never sent with a token about to lapse
+const TOKEN_REFRESH_MARGIN_SECS : i64 = 300;
+/// Assumed lifetime of an access token without a readable `exp` claim
+const DEFAULT_TOKEN_LIFETIME_SECS : i64 = 3600;
+/// Days before the refresh token expires at which warnings start, and at which they turn into errors
+const REFRESH_TOKEN_WARN_DAYS : i64 = 30;
+const REFRESH_TOKEN_CRITICAL_DAYS : i64 = 7;
+/// Longest range Eloverblik accepts in a single timeseries request
+pub const MAX_TIMESERIES_DAYS : i64 = 730;
+
+/// Which of Eloverblik's APIs a client talks to. The data endpoints and their models are shared.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Api {
+    /// `/customerapi`, for the metering points of the customer owning the refresh token
+    #[default]
+    Customer,
+    /// `/thirdpartyapi`, for the metering points customers have authorized a third party to access
+    ThirdParty,
+}
 
-const BASE_URL : &'static str = "https://api.eloverblik.dk/customerapi";
-const CACHE_KEY : &'static str = "ACCESS_TOKEN";
+impl Api {
+    pub fn base_url(&self) -> &'static str {
+        match self {
+            Api::Customer => BASE_URL,
+            Api::ThirdParty => THIRD_PARTY_BASE_URL,
+        }
+    }
+
+    fn cache_key(&self) -> &'static str {
+        match self {
+            Api::Customer => CACHE_KEY,
+            Api::ThirdParty => THIRD_PARTY_CACHE_KEY,
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct Client {
     http : reqwest::Client,
-    conf : Config,
+    api : Api,
+    base_url : String,
+    // Shared between clones, so a rotated refresh token reaches all of them
+    conf : Arc<ShardedLock<Config>>,
     cache : Option<Arc<ShardedLock<Box<dyn Cache<CString>>>>>,
+    limits : RateLimits,
+    retry : RetryPolicy,
     data : ClientData
 }
 
 #[derive(Clone, Debug)]
 struct ClientData {
-    pub token : Option<Arc<ShardedLock<Token>>>
+    // Shared between clones, so a token fetched by one is used by all
+    pub token : Arc<ShardedLock<Option<Token>>>,
+    // Held while a token is being fetched
+    pub refresh : Arc<tokio::sync::Mutex<()>>
 }
 
 #[derive(Clone, Debug)]
 struct Token {
-    pub token : String,
+    pub token : Secret,
+    /// Unix timestamp the token expires at
     pub expires_in : i64
 }
 
-// Limit most requests to 25 queries every 60 seconds
+impl Token {
+    fn new(token : Secret) -> Self {
+        let expires_in = jwt::expiry(token.expose()).unwrap_or_else(|| chrono::Utc::now().timestamp() + DEFAULT_TOKEN_LIFETIME_SECS);
+        Self {
+            token,
+            expires_in
+        }
+    }
+
+    fn is_fresh(&self) -> bool {
+        chrono::Utc::now().timestamp() < self.expires_in - TOKEN_REFRESH_MARGIN_SECS
+    }
+}
+
+// Requests are held back to Eloverblik's quotas, see ratelimit::RateLimits
 impl Client {
 
-    // Limit to 2 queries every 60 seconds
+    fn refresh_token(&self) -> Secret {
+        self.conf.read().unwrap_or_else(|err| err.into_inner()).refresh_token.clone()
+    }
+
+    /// Replaces the refresh token on this client and its clones, e.g. after it has been rotated. Access tokens from the old one are dropped.
+    pub fn set_refresh_token(&self, val : Secret) {
+        self.conf.write().unwrap_or_else(|err| err.into_inner()).refresh_token = val;
+        self.invalidate_access_token();
+    }
+
+    /// Drops the current access token from memory and the cache, so the next request fetches a new one
+    pub fn invalidate_access_token(&self) {
+        *self.data.token.write().unwrap_or_else(|err| err.into_inner()) = None;
+        self.forget_cached_token();
+    }
+
+    /// Hit and miss counts of the token cache, if there is one
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(|ch| ch.read().unwrap_or_else(|err| err.into_inner()).stats())
+    }
+
+    /// When the configured refresh token expires, if it carries an `exp` claim
+    pub fn refresh_token_expiry(&self) -> Option<DateTime<Utc>> {
+        jwt::expiry(self.refresh_token().expose()).and_then(|exp| Utc.timestamp_opt(exp, 0).single())
+    }
+
+    // Warnings get louder as the refresh token nears its expiry, and no request is made with an expired one
+    fn check_refresh_token(&self) -> Result<()> {
+        let expiry = match self.refresh_token_expiry() {
+            None => return Ok(()),
+            Some(val) => val
+        };
+
+        let days_left = (expiry - Utc::now()).num_days();
+        if expiry <= Utc::now() {
+            return Err(Error::RefreshTokenExpired(expiry));
+        } else if days_left < REFRESH_TOKEN_CRITICAL_DAYS {
+            error!(target:"eloverblik_client::auth", "Refresh token expires in {} days, at {}", days_left, expiry);
+        } else if days_left < REFRESH_TOKEN_WARN_DAYS {
+            warn!(target:"eloverblik_client::auth", "Refresh token expires in {} days, at {}", days_left, expiry);
+        }
+
+        Ok(())
+    }
+
     pub async fn auth(&self) -> Result<model::response::TokenResponse> {
-        let mut req = Request::new(reqwest::Method::GET, format!("{}/api/token", BASE_URL).parse().unwrap());
-        req.headers_mut().insert("Authorization", format!("Bearer {}", self.conf.refresh_token).parse().unwrap());
+        self.check_refresh_token()?;
+        let context = RequestContext::new("/api/token");
+        let mut req = self.build_request(self.http.get(format!("{}/api/token", self.base_url)), &context)?;
+        set_bearer(&mut req, &self.refresh_token())?;
 
-        let resp = self.check_response(self.http.execute(req).await);
+        self.execute(req, &self.limits.token, context).await
+    }
+
+    /// A valid access token, taken from memory, then the cache, and fetched from the token endpoint when neither has a fresh one
+    async fn access_token(&self) -> Result<Secret> {
+        if let Some(val) = self.fresh_token() {
+            return Ok(val);
+        }
 
-        match resp {
-            Ok(val) => {
-                let deserialised : Result<model::response::TokenResponse> = val.json().await.map_err(|err | err.into());
-                return deserialised;
+        // Single flight, the first caller refreshes while the rest wait here and pick up its token
+        let _refresh = self.data.refresh.lock().await;
+        if let Some(val) = self.fresh_token() {
+            return Ok(val);
+        }
+
+        let cached = self.cache.as_ref().and_then(|ch| {
+            let lock = ch.read().unwrap_or_else(|err| err.into_inner());
+            if lock.has_expired(self.api.cache_key()) {
+                None
+            } else {
+                lock.get(self.api.cache_key())
             }
-            Err(err) => {
-                return Err(err);
+        }).map(|val| Token::new(String::from(val).into())).filter(|val| val.is_fresh());
+
+        let token = match cached {
+            Some(val) => {
+                debug!(target:"eloverblik_client::auth", "Using cached token");
+                val
+            }
+            None => {
+                debug!(target:"eloverblik_client::auth", "Getting new token");
+                let token = Token::new(self.auth().await?.result);
+                if let Some(ch) = self.cache.as_ref() {
+                    let mut write_lock = ch.write().unwrap_or_else(|err| err.into_inner());
+                    write_lock.put(self.api.cache_key(), token.token.expose().to_owned().into(), Some(token.expires_in - TOKEN_REFRESH_MARGIN_SECS));
+                }
+                token
             }
         };
+
+        let val = token.token.clone();
+        *self.data.token.write().unwrap_or_else(|err| err.into_inner()) = Some(token);
+        Ok(val)
     }
 
-    async fn store_token(&self, token : String) {
+    fn fresh_token(&self) -> Option<Secret> {
+        self.data.token.read().unwrap_or_else(|err| err.into_inner()).as_ref().filter(|val| val.is_fresh()).map(|val| val.token.clone())
+    }
+
+    // Drops a token the API has refused, so the next request gets a new one. A token another caller already replaced is left alone.
+    fn invalidate_token(&self, refused : &Secret) {
+        let mut token = self.data.token.write().unwrap_or_else(|err| err.into_inner());
+        if token.as_ref().map(|val| val.token != *refused).unwrap_or(false) {
+            return;
+        }
+        *token = None;
+        self.forget_cached_token();
+    }
+
+    fn forget_cached_token(&self) {
         if let Some(ch) = self.cache.as_ref() {
-            let mut write_lock = ch.write().unwrap();
-            write_lock.put(CACHE_KEY, token.into(), None)
+            let mut write_lock = ch.write().unwrap_or_else(|err| err.into_inner());
+            write_lock.remove(self.api.cache_key());
         }
     }
 
-    async fn prepare_http_request(&self, req : &mut Request) {
-        let mut token = "".to_owned();
-        match &self.cache {
-            None => {
-                debug!(target:"eloverblik_client::auth", "Skipping cache, getting new token");
-                let resp = self.auth().await.unwrap();
-                token = resp.result;
-            }
-            Some(ch) => {
-                let mut get_new_token = false;
-                {
-                    let lock = ch.read().unwrap();
-                    get_new_token = lock.has_expired(CACHE_KEY);
-                }
+    // Sends a data request with an access token, getting a new token and trying once more if it is refused
+    async fn execute_authenticated<T : DeserializeOwned>(&self, req : Request, context : RequestContext) -> Result<T> {
+        let body = self.execute_authenticated_text(req, context.clone()).await?;
+        parse_body(&body, context)
+    }
 
-                if get_new_token {
-                    debug!(target:"eloverblik_client::auth", "Skipping cache, getting new token");
-                    let resp = self.auth().await.unwrap();
-                    token = resp.result.clone();
-                    // token = "dummy".to_owned();
-                    let mut write_lock = ch.write().unwrap();
-                    write_lock.put(CACHE_KEY, token.clone().into(), None);
-                } else {
-                    debug!(target:"eloverblik_client::auth", "Using cache");
-                    let lock = ch.read().unwrap();
-                    token = lock.get(CACHE_KEY).unwrap().clone().into();
-                }
+    // Like `execute_authenticated`, for the endpoints that do not answer with JSON
+    async fn execute_authenticated_text(&self, mut req : Request, context : RequestContext) -> Result<String> {
+        let retry_req = req.try_clone();
+        let token = self.access_token().await?;
+        set_bearer(&mut req, &token)?;
+
+        match (self.execute_text(req, &self.limits.data, &context).await, retry_req) {
+            (Err(Error::HttpStatus { status: 401, .. }), Some(mut retry_req)) => {
+                debug!(target:"eloverblik_client::auth", "Access token refused, getting a new one");
+                self.invalidate_token(&token);
+                set_bearer(&mut retry_req, &self.access_token().await?)?;
+                self.execute_text(retry_req, &self.limits.data, &context).await
             }
+            (resp, _) => resp
         }
+    }
 
-        req.headers_mut().insert("Authorization", format!("Bearer {}", token).parse().unwrap());
+    fn build_request(&self, builder : RequestBuilder, context : &RequestContext) -> Result<Request> {
+        builder.build().map_err(|source| Error::RequestFailed { context: context.clone(), source })
     }
 
-    // Handle rate limits
-    fn check_response(&self, resp : std::result::Result<Response, reqwest::Error>) -> Result<Response> {
-        match resp {
-            Ok(val) => {
-                match val.status().as_u16() {
-                    429 => Err(Error::ElOverblikRateLimited(chrono::Utc::now().timestamp() + 61)),
-                    503 => Err(Error::ElOverblikRateLimited(chrono::Utc::now().timestamp() + 61)),
-                    _ => {
-                        return Ok(val);
-                    }
-                }
-            },
-            Err(err) => {
-                panic!("{}", err);
+    // Sends the request and parses the JSON body, attaching `context` to whatever goes wrong
+    async fn execute<T : DeserializeOwned>(&self, req : Request, limiter : &RateLimiter, context : RequestContext) -> Result<T> {
+        let body = self.execute_text(req, limiter, &context).await?;
+        parse_body(&body, context)
+    }
+
+    async fn execute_text(&self, req : Request, limiter : &RateLimiter, context : &RequestContext) -> Result<String> {
+        let resp = self.send(req, limiter, context).await?;
+        resp.text().await.map_err(|source| Error::RequestFailed { context: context.clone(), source })
+    }
+
+    // Waits for the quota, then sends the request, retrying rate limits and, for idempotent calls, transient errors according to the retry policy
+    async fn send(&self, req : Request, limiter : &RateLimiter, context : &RequestContext) -> Result<Response> {
+        let resp = self.retry.send(req, context.idempotent, |attempt_req| async move {
+            limiter.acquire().await;
+            self.http.execute(attempt_req).await
+        }).await;
+        self.check_response(resp, context).await
+    }
+
+    // Handle rate limits and turn every other unsuccessful status into an error carrying the start of the body
+    async fn check_response(&self, resp : std::result::Result<Response, reqwest::Error>, context : &RequestContext) -> Result<Response> {
+        let resp = resp.map_err(|source| Error::RequestFailed { context: context.clone(), source })?;
+        let status = resp.status();
+
+        match status.as_u16() {
+            _ if retry::is_rate_limited(&resp) => Err(Error::ElOverblikRateLimited {
+                context: context.clone(),
+                retry_after: retry::retry_after(&resp).unwrap_or(chrono::Utc::now().timestamp() + 61)
+            }),
+            _ if status.is_success() => Ok(resp),
+            _ => {
+                let body = resp.text().await.unwrap_or_default();
+                Err(Error::HttpStatus {
+                    context: context.clone(),
+                    status: status.as_u16(),
+                    body: body_excerpt(&body)
+                })
             }
         }
     }
 
+    fn require_api(&self, api : Api, endpoint : &str) -> Result<()> {
+        if self.api != api {
+            return Err(Error::UnsupportedApi(endpoint.to_owned(), self.api));
+        }
+        Ok(())
+    }
+
+    /// Metering points of the customer. Only on the customer API, see [`Client::get_metering_points_for`] for third parties.
     pub async fn get_metering_points(&self) -> Result<GetMeteringPointsResponse> {
-        let mut req = Request::new(reqwest::Method::GET, format!("{}/api/meteringpoints/meteringpoints", BASE_URL).parse().unwrap());
-        self.prepare_http_request(&mut req).await;
-        let resp = self.http.execute(req).await;
-        let checked_resp = self.check_response(resp).unwrap();
+        self.require_api(Api::Customer, "/api/meteringpoints/meteringpoints")?;
+        let context = RequestContext::new("/api/meteringpoints/meteringpoints");
+        let req = self.build_request(self.http.get(format!("{}/api/meteringpoints/meteringpoints", self.base_url)), &context)?;
+
+        self.execute_authenticated(req, context).await
+    }
+
+    /// Like [`Client::get_metering_points`], but also returns the points the customer has not linked yet, see [`GetMeteringPointsResponse::without_relation`]
+    pub async fn get_all_metering_points(&self) -> Result<GetMeteringPointsResponse> {
+        self.require_api(Api::Customer, "/api/meteringpoints/meteringpoints")?;
+        let context = RequestContext::new("/api/meteringpoints/meteringpoints");
+        let req = self.build_request(self.http.get(format!("{}/api/meteringpoints/meteringpoints", self.base_url)).query(&[("includeAll", "true")]), &context)?;
 
-        checked_resp.json().await.map_err(|err| err.into())
+        self.execute_authenticated(req, context).await
+    }
+
+    /// Links metering points registered to the customer's CPR or CVR number to their account. Only on the customer API.
+    pub async fn add_relation_by_id(&self, request_payload : AddRelationRequest) -> Result<AddRelationResponse> {
+        self.require_api(Api::Customer, "/api/meteringpoints/meteringpoint/relation/add")?;
+        let context = RequestContext::new("/api/meteringpoints/meteringpoint/relation/add")
+            .with_metering_points(&request_payload.metering_points.metering_point)
+            .not_idempotent();
+        let req = self.build_request(self.http.post(format!("{}/api/meteringpoints/meteringpoint/relation/add", self.base_url)).json(&request_payload), &context)?;
+
+        self.execute_authenticated(req, context).await
+    }
+
+    /// Links a metering point that is not registered to the customer, using the web access code printed on the electricity bill.
+    /// Only on the customer API.
+    pub async fn add_relation_by_access_code(&self, metering_point_id : &str, web_access_code : &str) -> Result<AddRelationByAccessCodeResponse> {
+        self.require_api(Api::Customer, "/api/meteringpoints/meteringpoint/relation/add")?;
+        let endpoint = format!("/api/meteringpoints/meteringpoint/relation/add/{}/{}", metering_point_id, web_access_code);
+        // The access code is a credential, so it is left out of the endpoint and of the URL transport errors print
+        let context = RequestContext::new("/api/meteringpoints/meteringpoint/relation/add")
+            .with_metering_points(&[metering_point_id.to_owned()])
+            .not_idempotent();
+        let req = self.build_request(self.http.put(format!("{}{}", self.base_url, endpoint)), &context).map_err(Error::without_url)?;
+
+        self.execute_authenticated(req, context).await.map_err(Error::without_url)
+    }
+
+    /// Unlinks a metering point from the customer's account. Only on the customer API.
+    pub async fn delete_relation(&self, metering_point_id : &str) -> Result<DeleteRelationResponse> {
+        self.require_api(Api::Customer, "/api/meteringpoints/meteringpoint/relation")?;
+        let endpoint = format!("/api/meteringpoints/meteringpoint/relation/{}", metering_point_id);
+        let context = RequestContext::new(&endpoint)
+            .with_metering_points(&[metering_point_id.to_owned()])
+            .not_idempotent();
+        let req = self.build_request(self.http.delete(format!("{}{}", self.base_url, endpoint)), &context)?;
+
+        self.execute_authenticated(req, context).await
     }
 
     pub async fn get_metering_point_charges(&self, request_payload : GetMeteringPointChargesRequest) -> Result<GetMeteringPointChargesResponse> {
-        let mut req = self.http.request(reqwest::Method::POST, format!("{}/api/meteringpoints/meteringpoint/getcharges", BASE_URL))
-            .json(&request_payload)
-            .build().unwrap();
-        self.prepare_http_request(&mut req).await;
+        let context = RequestContext::new("/api/meteringpoints/meteringpoint/getcharges")
+            .with_metering_points(&request_payload.metering_points.metering_point);
+        let req = self.build_request(self.http.post(format!("{}/api/meteringpoints/meteringpoint/getcharges", self.base_url)).json(&request_payload), &context)?;
+
+        self.execute_authenticated(req, context).await
+    }
 
-        let resp = self.http.execute(req).await;
-        let checked_resp = self.check_response(resp).unwrap();
+    /// Grid operator, capacity, settlement and supplier details, beyond the summary [`Client::get_metering_points`] returns
+    pub async fn get_metering_point_details(&self, request_payload : GetMeteringPointDetailsRequest) -> Result<GetMeteringPointDetailsResponse> {
+        let context = RequestContext::new("/api/meteringpoints/meteringpoint/getdetails")
+            .with_metering_points(&request_payload.metering_points.metering_point);
+        let req = self.build_request(self.http.post(format!("{}/api/meteringpoints/meteringpoint/getdetails", self.base_url)).json(&request_payload), &context)?;
 
-        checked_resp.json().await.map_err(|err| err.into())
+        self.execute_authenticated(req, context).await
     }
 
-    pub async fn get_metering_data_timeseries(&self, request_payload : GetMeteringDataTimeSeriesRequest, start_date : &str, end_date : &str, aggregation : &str) -> Result<GetMeteringDataTimeSeriesResponse> {
-        let mut req = self.http.request(reqwest::Method::POST, format!("{}/api/meterdata/gettimeseries/{}/{}/{}", BASE_URL, start_date, end_date, aggregation))
-            .json(&request_payload)
-            .build().unwrap();
-        self.prepare_http_request(&mut req).await;
+    /// Fetches `[start_date, end_date)`. Eloverblik only accepts up to [`MAX_TIMESERIES_DAYS`] per request, see [`Client::get_metering_data_timeseries_chunked`] for longer ranges.
+    pub async fn get_metering_data_timeseries(&self, request_payload : GetMeteringDataTimeSeriesRequest, start_date : NaiveDate, end_date : NaiveDate, aggregation : Aggregation) -> Result<GetMeteringDataTimeSeriesResponse> {
+        validate_range(start_date, end_date)?;
+        if (end_date - start_date).num_days() > MAX_TIMESERIES_DAYS {
+            return Err(Error::InvalidDateRange(format!("{} to {} is longer than {} days", start_date, end_date, MAX_TIMESERIES_DAYS)));
+        }
 
-        let resp = self.http.execute(req).await;
-        let checked_resp = self.check_response(resp).unwrap();
+        let endpoint = format!("/api/meterdata/gettimeseries/{}/{}/{}", start_date.format("%Y-%m-%d"), end_date.format("%Y-%m-%d"), aggregation);
+        let context = RequestContext::new(&endpoint)
+            .with_metering_points(&request_payload.metering_points.metering_point);
+        let req = self.build_request(self.http.post(format!("{}{}", self.base_url, endpoint)).json(&request_payload), &context)?;
 
-        checked_resp.json().await.map_err(|err| err.into())
+        self.execute_authenticated(req, context).await
     }
 
+    /// Fetches an arbitrarily long range by splitting it into chunks of at most `chunk_days` (capped at [`MAX_TIMESERIES_DAYS`]),
+    /// and stitching the periods of each metering point back into a single document.
+    /// A chunk Eloverblik reports an error for is left out of the document and returned alongside it, so the other chunks are kept.
+    pub async fn get_metering_data_timeseries_chunked(&self, request_payload : GetMeteringDataTimeSeriesRequest, start_date : NaiveDate, end_date : NaiveDate, aggregation : Aggregation, chunk_days : i64) -> Result<(GetMeteringDataTimeSeriesResponse, Vec<ChunkError>)> {
+        validate_range(start_date, end_date)?;
+        let mut payload = GetMeteringDataTimeSeriesResponse {
+            result: Vec::new()
+        };
+        let mut failed = Vec::new();
+
+        for (chunk_start, chunk_end) in split_range(start_date, end_date, chunk_days) {
+            debug!(target:"eloverblik_client::timeseries", "Fetching chunk {} to {}", chunk_start, chunk_end);
+
+            let resp = self.get_metering_data_timeseries(request_payload.clone(), chunk_start, chunk_end, aggregation).await?;
+
+            for result in resp.result {
+                if let Some(error) = result.api_error() {
+                    failed.push(ChunkError { start: chunk_start, end: chunk_end, error });
+                    continue;
+                }
+
+                match payload.result.iter_mut().find(|existing| existing.id == result.id) {
+                    None => payload.result.push(result),
+                    Some(existing) => existing.my_energy_data_market_document.merge(result.my_energy_data_market_document)
+                }
+            }
+        }
+
+        Ok((payload, failed))
+    }
+
+    /// The timeseries in `[start_date, end_date)` as the CSV the Eloverblik portal exports, see [`export::parse_timeseries_csv`] to read it back
+    pub async fn export_timeseries(&self, request_payload : GetMeteringDataTimeSeriesRequest, start_date : NaiveDate, end_date : NaiveDate, aggregation : Aggregation) -> Result<String> {
+        validate_range(start_date, end_date)?;
+        let context = RequestContext::new("/api/meterdata/timeseries/export")
+            .with_metering_points(&request_payload.metering_points.metering_point);
+        let payload = ExportTimeSeriesRequest {
+            metering_points: request_payload.metering_points,
+            date_from: start_date.format("%Y-%m-%d").to_string(),
+            date_to: end_date.format("%Y-%m-%d").to_string(),
+            aggregation
+        };
+        let req = self.build_request(self.http.post(format!("{}/api/meterdata/timeseries/export", self.base_url)).json(&payload), &context)?;
+
+        self.execute_authenticated_text(req, context).await
+    }
+
+    /// Register readings of the physical meters in `[start_date, end_date)`, for reconciling with the meter itself and with supplier bills.
+    /// Limited to [`MAX_TIMESERIES_DAYS`] per request like the timeseries.
+    pub async fn get_meter_readings(&self, request_payload : GetMeterReadingsRequest, start_date : NaiveDate, end_date : NaiveDate) -> Result<GetMeterReadingsResponse> {
+        validate_range(start_date, end_date)?;
+        if (end_date - start_date).num_days() > MAX_TIMESERIES_DAYS {
+            return Err(Error::InvalidDateRange(format!("{} to {} is longer than {} days", start_date, end_date, MAX_TIMESERIES_DAYS)));
+        }
+
+        let endpoint = format!("/api/meterdata/getmeterreadings/{}/{}", start_date.format("%Y-%m-%d"), end_date.format("%Y-%m-%d"));
+        let context = RequestContext::new(&endpoint)
+            .with_metering_points(&request_payload.metering_points.metering_point);
+        let req = self.build_request(self.http.post(format!("{}{}", self.base_url, endpoint)).json(&request_payload), &context)?;
+
+        self.execute_authenticated(req, context).await
+    }
+
+    /// Authorizations customers have granted the third party. Only on the third-party API.
+    pub async fn get_authorizations(&self) -> Result<GetAuthorizationsResponse> {
+        self.require_api(Api::ThirdParty, "/api/authorization/authorizations")?;
+        let context = RequestContext::new("/api/authorization/authorizations");
+        let req = self.build_request(self.http.get(format!("{}/api/authorization/authorizations", self.base_url)), &context)?;
+
+        self.execute_authenticated(req, context).await
+    }
+
+    /// Metering points covered by authorizations, looked up by authorization id, customer key or customer CVR. Only on the third-party API.
+    pub async fn get_authorization_metering_points(&self, scope : AuthorizationScope, identifier : &str) -> Result<GetMeteringPointsResponse> {
+        let endpoint = format!("/api/authorization/authorization/meteringpoints/{}/{}", scope, identifier);
+        self.require_api(Api::ThirdParty, &endpoint)?;
+        let context = RequestContext::new(&endpoint);
+        let req = self.build_request(self.http.get(format!("{}{}", self.base_url, endpoint)), &context)?;
+
+        self.execute_authenticated(req, context).await
+    }
+
+    pub async fn get_metering_points_for(&self, authorization : &Authorization) -> Result<GetMeteringPointsResponse> {
+        self.get_authorization_metering_points(AuthorizationScope::CustomerKey, &authorization.customer_key).await
+    }
+
+    pub async fn get_metering_point_charges_for(&self, authorization : &Authorization) -> Result<GetMeteringPointChargesResponse> {
+        let metering_points = self.authorization_metering_point_ids(authorization).await?;
+        self.get_metering_point_charges(GetMeteringPointChargesRequest { metering_points }).await
+    }
+
+    /// Timeseries of every metering point of an authorization, fetched in as few requests as Eloverblik allows.
+    /// Failed chunks are returned like in [`Client::get_metering_data_timeseries_chunked`].
+    pub async fn get_metering_data_timeseries_for(&self, authorization : &Authorization, start_date : NaiveDate, end_date : NaiveDate, aggregation : Aggregation) -> Result<(GetMeteringDataTimeSeriesResponse, Vec<ChunkError>)> {
+        let metering_points = self.authorization_metering_point_ids(authorization).await?;
+        self.get_metering_data_timeseries_chunked(GetMeteringDataTimeSeriesRequest { metering_points }, start_date, end_date, aggregation, MAX_TIMESERIES_DAYS).await
+    }
+
+    async fn authorization_metering_point_ids(&self, authorization : &Authorization) -> Result<MeteringPoints> {
+        let resp = self.get_metering_points_for(authorization).await?;
+        Ok(MeteringPoints {
+            metering_point: resp.result.into_iter().map(|val| val.metering_point_id).collect()
+        })
+    }
+
+}
+
+fn parse_body<T : DeserializeOwned>(body : &str, context : RequestContext) -> Result<T> {
+    serde_json::from_str(body).map_err(|source| Error::InvalidResponse {
+        context,
+        body: body_excerpt(body),
+        source
+    })
+}
+
+fn set_bearer(req : &mut Request, token : &Secret) -> Result<()> {
+    let mut val = HeaderValue::from_str(&format!("Bearer {}", token.expose())).map_err(|_| Error::InvalidToken)?;
+    val.set_sensitive(true);
+    req.headers_mut().insert(reqwest::header::AUTHORIZATION, val);
+    Ok(())
+}
+
+/// Checks that `[start, end)` is non-empty and does not reach past today, as Eloverblik has no data for the future.
+pub fn validate_range(start : NaiveDate, end : NaiveDate) -> Result<()> {
+    if start >= end {
+        return Err(Error::InvalidDateRange(format!("start {} is not before end {}", start, end)));
+    }
+    if end > chrono::Utc::now().date_naive() {
+        return Err(Error::InvalidDateRange(format!("end {} is in the future", end)));
+    }
+
+    Ok(())
+}
+
+/// Splits `[start, end)` into consecutive ranges of at most `chunk_days`, capped at [`MAX_TIMESERIES_DAYS`].
+pub fn split_range(start : NaiveDate, end : NaiveDate, chunk_days : i64) -> Vec<(NaiveDate, NaiveDate)> {
+    let chunk = chrono::Duration::days(chunk_days.clamp(1, MAX_TIMESERIES_DAYS));
+    let mut payload = Vec::new();
+    let mut chunk_start = start;
+
+    while chunk_start < end {
+        let chunk_end = (chunk_start + chunk).min(end);
+        payload.push((chunk_start, chunk_end));
+        chunk_start = chunk_end;
+    }
+
+    payload
 }
 
 pub struct ClientBuilder {
@@ -165,8 +555,21 @@ impl ClientBuilder {
         self
     }
 
+    /// Overrides where requests go, e.g. to a mock server. Defaults to the public customer API.
+    pub fn add_base_url(mut self, val : &str) -> ClientBuilder {
+        self.inner.base_url = val.trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// Switches to another of Eloverblik's APIs, resetting the base URL to its default. Call [`ClientBuilder::add_base_url`] after this to override it.
+    pub fn add_api(mut self, val : Api) -> ClientBuilder {
+        self.inner.api = val;
+        self.inner.base_url = val.base_url().to_owned();
+        self
+    }
+
     pub fn add_config(mut self, val : Config) -> ClientBuilder {
-        self.inner.conf = val;
+        self.inner.conf = Arc::new(ShardedLock::new(val));
         self
     }
 
@@ -174,20 +577,35 @@ impl ClientBuilder {
         self.inner.cache = Some(Arc::new(ShardedLock::new(val)));
         self
     }
+
+    pub fn add_rate_limits(mut self, val : RateLimits) -> ClientBuilder {
+        self.inner.limits = val;
+        self
+    }
+
+    pub fn add_retry_policy(mut self, val : RetryPolicy) -> ClientBuilder {
+        self.inner.retry = val;
+        self
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct Config {
-    pub refresh_token : String
+    pub refresh_token : Secret
 }
 
 pub fn new_default_client(conf : Config) -> Client {
     Client {
         http: reqwest::Client::default(),
-        conf,
+        api: Api::Customer,
+        base_url: BASE_URL.to_owned(),
+        conf: Arc::new(ShardedLock::new(conf)),
         cache: None,
+        limits: RateLimits::default(),
+        retry: RetryPolicy::default(),
         data: ClientData {
-            token: None
+            token: Arc::new(ShardedLock::new(None)),
+            refresh: Arc::new(tokio::sync::Mutex::new(()))
         }
     }
 }
@@ -208,4 +626,262 @@ mod tests {
         // let result = add(2, 2);
         // assert_eq!(result, 4);
     }
+
+    #[test]
+    fn expired_refresh_token_is_refused() {
+        use base64::Engine;
+
+        let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(r#"{"exp":1600000000}"#);
+        let client = new_default_client(Config {
+            refresh_token: format!("eyJhbGciOiJub25lIn0.{}.", payload).into()
+        });
+
+        assert_eq!(client.refresh_token_expiry(), Utc.timestamp_opt(1600000000, 0).single());
+        assert!(matches!(client.check_refresh_token(), Err(Error::RefreshTokenExpired(_))));
+    }
+
+    #[test]
+    fn split_range_caps_chunks() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+
+        assert_eq!(split_range(date(2023, 1, 1), date(2023, 1, 10), 4), vec![
+            (date(2023, 1, 1), date(2023, 1, 5)),
+            (date(2023, 1, 5), date(2023, 1, 9)),
+            (date(2023, 1, 9), date(2023, 1, 10)),
+        ]);
+        assert_eq!(split_range(date(2020, 1, 1), date(2023, 1, 1), 10_000).len(), 2);
+        assert!(split_range(date(2023, 1, 1), date(2023, 1, 1), 30).is_empty());
+    }
+
+    #[test]
+    fn validate_range_rejects_empty_and_future_ranges() {
+        let today = chrono::Utc::now().date_naive();
+
+        assert!(validate_range(today - chrono::Duration::days(1), today).is_ok());
+        assert!(matches!(validate_range(today, today), Err(Error::InvalidDateRange(_))));
+        assert!(matches!(validate_range(today, today + chrono::Duration::days(1)), Err(Error::InvalidDateRange(_))));
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    async fn refused_token_is_replaced_once() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, ResponseTemplate};
+
+        let server = test_support::start_mock_server().await;
+        Mock::given(method("GET"))
+            .and(path("/api/meteringpoints/meteringpoints"))
+            .respond_with(ResponseTemplate::new(401))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+
+        let client = new_builder()
+            .add_base_url(&server.uri())
+            .add_retry_policy(RetryPolicy::none())
+            .build();
+        let resp = client.get_metering_points().await.unwrap();
+        assert_eq!(resp.result[0].metering_point_id, test_support::METERING_POINT_ID);
+
+        let token_requests = server.received_requests().await.unwrap().iter().filter(|req| req.url.path() == "/api/token").count();
+        assert_eq!(token_requests, 2);
+
+        // The new token is kept in memory and reused
+        client.get_metering_points().await.unwrap();
+        let token_requests = server.received_requests().await.unwrap().iter().filter(|req| req.url.path() == "/api/token").count();
+        assert_eq!(token_requests, 2);
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    async fn concurrent_requests_share_one_token_request() {
+        let server = test_support::start_mock_server().await;
+        let client = new_builder()
+            .add_base_url(&server.uri())
+            .add_retry_policy(RetryPolicy::none())
+            .build();
+
+        let requests = (0..5).map(|_| {
+            let client = client.clone();
+            tokio::spawn(async move { client.get_metering_points().await })
+        }).collect::<Vec<_>>();
+        for req in requests {
+            req.await.unwrap().unwrap();
+        }
+
+        let token_requests = server.received_requests().await.unwrap().iter().filter(|req| req.url.path() == "/api/token").count();
+        assert_eq!(token_requests, 1);
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    async fn third_party_fetches_per_authorization() {
+        let server = test_support::start_mock_server().await;
+        let client = new_builder()
+            .add_api(Api::ThirdParty)
+            .add_base_url(&server.uri())
+            .add_retry_policy(RetryPolicy::none())
+            .build();
+
+        let authorizations = client.get_authorizations().await.unwrap();
+        let charges = client.get_metering_point_charges_for(&authorizations.result[0]).await.unwrap();
+        assert_eq!(charges.result[0].id, test_support::METERING_POINT_ID);
+        assert!(matches!(client.get_metering_points().await, Err(Error::UnsupportedApi(_, Api::ThirdParty))));
+
+        let requested = server.received_requests().await.unwrap();
+        assert!(requested.iter().any(|req| req.url.path() == "/api/authorization/authorization/meteringpoints/customerKey/test-customer-key"));
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    async fn failed_chunk_keeps_the_other_chunks() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, ResponseTemplate};
+
+        let server = test_support::start_mock_server().await;
+        Mock::given(method("POST"))
+            .and(path("/api/meterdata/gettimeseries/2023-08-02/2023-08-03/Hour"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(format!(r#"{{"result": [{{
+                "MyEnergyData_MarketDocument": null, "success": false, "errorCode": 50000,
+                "errorText": "InternalServerError", "id": "{}", "stackTrace": null
+            }}]}}"#, test_support::METERING_POINT_ID), "application/json"))
+            .with_priority(1)
+            .mount(&server)
+            .await;
+
+        let client = new_builder()
+            .add_base_url(&server.uri())
+            .add_retry_policy(RetryPolicy::none())
+            .build();
+        let date = |d| NaiveDate::from_ymd_opt(2023, 8, d).unwrap();
+        let (resp, failed) = client.get_metering_data_timeseries_chunked(GetMeteringDataTimeSeriesRequest {
+            metering_points: MeteringPoints { metering_point: vec![test_support::METERING_POINT_ID.to_owned()] }
+        }, date(1), date(4), Aggregation::Hour, 1).await.unwrap();
+
+        assert_eq!(failed.len(), 1);
+        assert_eq!((failed[0].start, failed[0].end), (date(2), date(3)));
+        assert_eq!(failed[0].error.code, error::ApiErrorCode::InternalServerError);
+        let (answered, errors) = resp.split();
+        assert!(errors.is_empty());
+        assert_eq!(answered[0].my_energy_data_market_document.time_series[0].period.len(), 1);
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    async fn exported_csv_is_parsed_back() {
+        let server = test_support::start_mock_server().await;
+        let client = new_builder()
+            .add_base_url(&server.uri())
+            .add_retry_policy(RetryPolicy::none())
+            .build();
+
+        let start = NaiveDate::from_ymd_opt(2023, 8, 1).unwrap();
+        let csv = client.export_timeseries(GetMeteringDataTimeSeriesRequest {
+            metering_points: MeteringPoints { metering_point: vec![test_support::METERING_POINT_ID.to_owned()] }
+        }, start, start + chrono::Duration::days(2), Aggregation::Hour).await.unwrap();
+
+        let doc = export::parse_timeseries_csv(&csv).unwrap();
+        let period = &doc.time_series[0].period[0];
+        assert_eq!(period.time_interval.start, "2023-08-01T22:00:00Z");
+        assert_eq!(period.point[1].out_quantity_quantity, "0.25");
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    async fn relations_are_added_and_deleted() {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, ResponseTemplate};
+
+        let server = test_support::start_mock_server().await;
+        let unrelated = test_support::METERING_POINTS_FIXTURE.replace("\"hasRelation\": true", "\"hasRelation\": false");
+        Mock::given(method("GET"))
+            .and(path("/api/meteringpoints/meteringpoints"))
+            .and(query_param("includeAll", "true"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(unrelated, "application/json"))
+            .with_priority(1)
+            .mount(&server)
+            .await;
+
+        let client = new_builder()
+            .add_base_url(&server.uri())
+            .add_retry_policy(RetryPolicy::none())
+            .build();
+
+        assert!(client.get_metering_points().await.unwrap().without_relation().is_empty());
+        let missing = client.get_all_metering_points().await.unwrap().without_relation();
+        assert_eq!(missing, vec![test_support::METERING_POINT_ID.to_owned()]);
+
+        let (linked, failed) = client.add_relation_by_id(AddRelationRequest { metering_points: MeteringPoints { metering_point: missing } }).await.unwrap().split();
+        assert_eq!(linked[0].id, test_support::METERING_POINT_ID);
+        assert!(failed.is_empty());
+
+        client.add_relation_by_access_code(test_support::METERING_POINT_ID, "12345678").await.unwrap();
+        assert!(client.delete_relation(test_support::METERING_POINT_ID).await.unwrap().result);
+
+        let third_party = new_builder().add_api(Api::ThirdParty).add_base_url(&server.uri()).build();
+        assert!(matches!(third_party.delete_relation(test_support::METERING_POINT_ID).await, Err(Error::UnsupportedApi(_, Api::ThirdParty))));
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    async fn access_code_is_left_out_of_transport_errors() {
+        use std::time::Duration;
+        use wiremock::matchers::{method, path_regex};
+        use wiremock::{Mock, ResponseTemplate};
+
+        let server = test_support::start_mock_server().await;
+        Mock::given(method("PUT"))
+            .and(path_regex(r"^/api/meteringpoints/meteringpoint/relation/add/"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+            .with_priority(1)
+            .mount(&server)
+            .await;
+
+        let client = new_builder()
+            .add_http(reqwest::Client::builder().timeout(Duration::from_millis(200)).build().unwrap())
+            .add_base_url(&server.uri())
+            .add_retry_policy(RetryPolicy::none())
+            .build();
+
+        let err = client.add_relation_by_access_code(test_support::METERING_POINT_ID, "12345678").await.unwrap_err();
+        assert!(matches!(err, Error::RequestFailed { .. }));
+        assert!(!format!("{} {:?}", err, err).contains("12345678"));
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    async fn relation_changes_are_only_retried_when_rate_limited() {
+        use std::time::Duration;
+        use wiremock::matchers::{method, path_regex};
+        use wiremock::{Mock, ResponseTemplate};
+
+        let server = test_support::start_mock_server().await;
+        Mock::given(method("PUT"))
+            .and(path_regex(r"^/api/meteringpoints/meteringpoint/relation/add/"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+            .with_priority(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path_regex(r"^/api/meteringpoints/meteringpoint/relation/"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .with_priority(1)
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = new_builder()
+            .add_http(reqwest::Client::builder().timeout(Duration::from_millis(200)).build().unwrap())
+            .add_base_url(&server.uri())
+            .add_retry_policy(RetryPolicy { max_attempts: 3, initial_backoff: Duration::from_millis(10), max_backoff: Duration::from_millis(10) })
+            .build();
+
+        // The change may have been applied before the timeout, so it is not sent again
+        assert!(matches!(client.add_relation_by_access_code(test_support::METERING_POINT_ID, "12345678").await, Err(Error::RequestFailed { .. })));
+        assert!(client.delete_relation(test_support::METERING_POINT_ID).await.unwrap().result);
+        server.verify().await;
+    }
 }
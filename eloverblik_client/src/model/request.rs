@@ -6,15 +6,98 @@ pub struct GetMeteringPointChargesRequest {
     pub metering_points: MeteringPoints
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetMeteringPointDetailsRequest {
+    pub metering_points: MeteringPoints
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetMeteringDataTimeSeriesRequest {
     pub metering_points: MeteringPoints
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportTimeSeriesRequest {
+    pub metering_points: MeteringPoints,
+    /// YYYY-MM-DD
+    pub date_from: String,
+    /// YYYY-MM-DD, exclusive
+    pub date_to: String,
+    pub aggregation: Aggregation
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetMeterReadingsRequest {
+    pub metering_points: MeteringPoints
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddRelationRequest {
+    pub metering_points: MeteringPoints
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MeteringPoints {
     pub metering_point: Vec<String>
 }
 
+
+/// Resolution of the timeseries returned by Eloverblik
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Aggregation {
+    Actual,
+    Quarter,
+    Hour,
+    Day,
+    Month,
+    Year,
+}
+
+impl Aggregation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Aggregation::Actual => "Actual",
+            Aggregation::Quarter => "Quarter",
+            Aggregation::Hour => "Hour",
+            Aggregation::Day => "Day",
+            Aggregation::Month => "Month",
+            Aggregation::Year => "Year",
+        }
+    }
+}
+
+impl std::fmt::Display for Aggregation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What a third-party authorization lookup is keyed on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuthorizationScope {
+    AuthorizationId,
+    CustomerKey,
+    CustomerCvr,
+}
+
+impl AuthorizationScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthorizationScope::AuthorizationId => "authorizationId",
+            AuthorizationScope::CustomerKey => "customerKey",
+            AuthorizationScope::CustomerCvr => "customerCVR",
+        }
+    }
+}
+
+impl std::fmt::Display for AuthorizationScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -1,9 +1,12 @@
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Duration, Months, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
+use crate::error::ApiError;
+use crate::types::secret::Secret;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TokenResponse {
-    pub result : String
+    pub result : Secret
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -43,6 +46,98 @@ pub struct GetMeteringPointsResponseResult {
     pub child_metering_points: Vec<Option<serde_json::Value>>,
 }
 
+impl GetMeteringPointsResponseResult {
+    /// Ids and types of the child metering points, e.g. production points attached to a consumption point
+    pub fn child_metering_points(&self) -> Vec<(String, String)> {
+        self.child_metering_points.iter()
+            .flatten()
+            .filter_map(|child| {
+                let id = child.get("meteringPointId")?.as_str()?.to_owned();
+                let type_of_mp = child.get("typeOfMP").and_then(|val| val.as_str()).unwrap_or_default().to_owned();
+                Some((id, type_of_mp))
+            })
+            .collect()
+    }
+}
+
+impl GetMeteringPointsResponse {
+    /// Ids of the metering points the customer can see but has not linked to their account yet
+    pub fn without_relation(&self) -> Vec<String> {
+        self.result.iter()
+            .filter(|point| !point.has_relation)
+            .map(|point| point.metering_point_id.clone())
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddRelationResponse {
+    pub result: Vec<AddRelationResponseResult>,
+}
+
+impl AddRelationResponse {
+    /// Splits the results into the metering points that were linked and the ones Eloverblik reported an error for
+    pub fn split(self) -> (Vec<AddRelationResponseResult>, Vec<ApiError>) {
+        split_results(self.result, |val| val.api_error())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddRelationResponseResult {
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub result: String,
+    pub success: bool,
+    pub error_code: i64,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub error_text: String,
+    pub id: String,
+    pub stack_trace: Value,
+}
+
+impl AddRelationResponseResult {
+    pub fn api_error(&self) -> Option<ApiError> {
+        api_error(&self.id, self.success, self.error_code, &self.error_text)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddRelationByAccessCodeResponse {
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub result: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteRelationResponse {
+    pub result: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetAuthorizationsResponse {
+    pub result: Vec<Authorization>,
+}
+
+/// Access a customer has granted a third party through the Eloverblik portal
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Authorization {
+    pub id: String,
+    pub third_party_name: String,
+    pub valid_from: String,
+    pub valid_to: String,
+    pub customer_name: Option<String>,
+    #[serde(rename = "customerCVR")]
+    pub customer_cvr: Option<String>,
+    pub customer_key: String,
+    #[serde(default)]
+    pub include_future_metering_points: bool,
+    pub time_stamp: Option<String>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetMeteringPointChargesResponse {
@@ -52,15 +147,37 @@ pub struct GetMeteringPointChargesResponse {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetMeteringPointChargesResponseResult {
+    #[serde(deserialize_with = "null_as_default")]
     pub result: GetMeteringPointChargesResponseResultResult,
     pub success: bool,
     pub error_code: i64,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub error_text: String,
     pub id: String,
     pub stack_trace: Value,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+impl GetMeteringPointChargesResponse {
+    /// Splits the results into the metering points that were answered and the ones Eloverblik reported an error for
+    pub fn split(self) -> (Vec<GetMeteringPointChargesResponseResult>, Vec<ApiError>) {
+        split_results(self.result, |val| val.api_error())
+    }
+}
+
+impl GetMeteringPointChargesResponseResult {
+    pub fn api_error(&self) -> Option<ApiError> {
+        api_error(&self.id, self.success, self.error_code, &self.error_text)
+    }
+
+    pub fn into_result(self) -> Result<GetMeteringPointChargesResponseResultResult, ApiError> {
+        match self.api_error() {
+            None => Ok(self.result),
+            Some(err) => Err(err)
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetMeteringPointChargesResponseResultResult {
     pub fees: Vec<Value>,
@@ -73,31 +190,36 @@ impl GetMeteringPointChargesResponseResultResult {
     pub fn get_full_price(&self, cost : f64, pos : String, kwh : f64) -> f64 {
         let mut total = cost;
 
+        for (_, price) in self.get_tariff_prices(&pos) {
+            total += price * kwh;
+        }
+
+        total
+    }
+
+    /// Per kWh price of each tariff applicable to the given hour position, keyed by tariff name.
+    pub fn get_tariff_prices(&self, pos : &str) -> Vec<(String, f64)> {
+        let mut payload = Vec::new();
+
         for tariff in &self.tariffs {
             match tariff.name.as_str() {
-                "Transmissions nettarif" => {
-                    total = total + (tariff.prices.last().unwrap().price * kwh);
-                },
-                "Systemtarif" => {
-                    total = total + (tariff.prices.last().unwrap().price * kwh);
-                },
-                "Elafgift" => {
-                    total = total + (tariff.prices.last().unwrap().price * kwh);
+                "Transmissions nettarif" | "Systemtarif" | "Elafgift" => {
+                    if let Some(price) = tariff.prices.last() {
+                        payload.push((tariff.name.clone(), price.price));
+                    }
                 },
                 "Nettarif C time" => {
                     for price in &tariff.prices {
-                        if price.position.eq(&pos) {
-                            total = total + (price.price * kwh);
+                        if price.position.eq(pos) {
+                            payload.push((tariff.name.clone(), price.price));
                         }
                     }
                 },
-
-
                 _ => {}
             }
         }
 
-        total
+        payload
     }
 }
 
@@ -133,6 +255,106 @@ pub struct GetMeteringPointChargesResponseResultResultTariffPrice {
     pub price: f64,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetMeteringPointDetailsResponse {
+    pub result: Vec<GetMeteringPointDetailsResponseResult>,
+}
+
+impl GetMeteringPointDetailsResponse {
+    /// Splits the results into the metering points that were answered and the ones Eloverblik reported an error for
+    pub fn split(self) -> (Vec<GetMeteringPointDetailsResponseResult>, Vec<ApiError>) {
+        split_results(self.result, |val| val.api_error())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetMeteringPointDetailsResponseResult {
+    #[serde(deserialize_with = "null_as_default")]
+    pub result: MeteringPointDetails,
+    pub success: bool,
+    pub error_code: i64,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub error_text: String,
+    pub id: String,
+    pub stack_trace: Value,
+}
+
+impl GetMeteringPointDetailsResponseResult {
+    pub fn api_error(&self) -> Option<ApiError> {
+        api_error(&self.id, self.success, self.error_code, &self.error_text)
+    }
+
+    pub fn into_result(self) -> Result<MeteringPointDetails, ApiError> {
+        match self.api_error() {
+            None => Ok(self.result),
+            Some(err) => Err(err)
+        }
+    }
+}
+
+/// Everything Eloverblik knows about a metering point. Fields the grid operator has not filled in are `None`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MeteringPointDetails {
+    pub metering_point_id: String,
+    pub parent_metering_point_id: Option<String>,
+    #[serde(rename = "typeOfMP")]
+    pub type_of_mp: Option<String>,
+    #[serde(rename = "subTypeOfMP")]
+    pub sub_type_of_mp: Option<String>,
+    #[serde(rename = "physicalStatusOfMP")]
+    pub physical_status_of_mp: Option<String>,
+    pub energy_time_series_measure_unit: Option<String>,
+    /// Estimated annual consumption in kWh
+    pub estimated_annual_volume: Option<String>,
+    pub settlement_method: Option<String>,
+    pub meter_number: Option<String>,
+    pub grid_operator_name: Option<String>,
+    pub metering_grid_area_identification: Option<String>,
+    pub net_settlement_group: Option<String>,
+    pub consumer_category: Option<String>,
+    #[serde(rename = "powerLimitKW")]
+    pub power_limit_kw: Option<String>,
+    pub power_limit_a: Option<String>,
+    /// Contracted connection capacity in kW
+    pub mp_capacity: Option<String>,
+    pub mp_connection_type: Option<String>,
+    pub disconnection_type: Option<String>,
+    pub product: Option<String>,
+    pub meter_reading_occurrence: Option<String>,
+    pub mp_reading_characteristics: Option<String>,
+    pub meter_counter_digits: Option<String>,
+    pub meter_counter_multiply_factor: Option<String>,
+    pub meter_counter_unit: Option<String>,
+    pub meter_counter_type: Option<String>,
+    pub balance_supplier_name: Option<String>,
+    pub balance_supplier_start_date: Option<String>,
+    pub tax_reduction: Option<String>,
+    pub tax_settlement_date: Option<String>,
+    pub mp_relation_type: Option<String>,
+    pub consumer_start_date: Option<String>,
+    pub street_code: Option<String>,
+    pub street_name: Option<String>,
+    pub building_number: Option<String>,
+    pub floor_id: Option<String>,
+    pub room_id: Option<String>,
+    pub postcode: Option<String>,
+    pub city_name: Option<String>,
+    pub city_sub_division_name: Option<String>,
+    pub municipality_code: Option<String>,
+    pub location_description: Option<String>,
+    pub first_consumer_party_name: Option<String>,
+    pub second_consumer_party_name: Option<String>,
+    #[serde(rename = "consumerCVR")]
+    pub consumer_cvr: Option<String>,
+    #[serde(rename = "dataAccessCVR")]
+    pub data_access_cvr: Option<String>,
+    pub contact_addresses: Option<Value>,
+    pub child_metering_points: Option<Value>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetMeteringDataTimeSeriesResponse {
@@ -142,17 +364,117 @@ pub struct GetMeteringDataTimeSeriesResponse {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetMeteringDataTimeSeriesResponseResult {
-    #[serde(rename = "MyEnergyData_MarketDocument")]
+    #[serde(rename = "MyEnergyData_MarketDocument", deserialize_with = "null_as_default")]
     pub my_energy_data_market_document: MyEnergyDataMarketDocument,
     pub success: bool,
     pub error_code: i64,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub error_text: String,
     pub id: String,
     pub stack_trace: Value,
 }
 
+impl GetMeteringDataTimeSeriesResponse {
+    /// Splits the results into the metering points that were answered and the ones Eloverblik reported an error for
+    pub fn split(self) -> (Vec<GetMeteringDataTimeSeriesResponseResult>, Vec<ApiError>) {
+        split_results(self.result, |val| val.api_error())
+    }
+}
+
+impl GetMeteringDataTimeSeriesResponseResult {
+    pub fn api_error(&self) -> Option<ApiError> {
+        api_error(&self.id, self.success, self.error_code, &self.error_text)
+    }
+
+    pub fn into_result(self) -> Result<MyEnergyDataMarketDocument, ApiError> {
+        match self.api_error() {
+            None => Ok(self.my_energy_data_market_document),
+            Some(err) => Err(err)
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetMeterReadingsResponse {
+    pub result: Vec<GetMeterReadingsResponseResult>,
+}
+
+impl GetMeterReadingsResponse {
+    /// Splits the results into the metering points that were answered and the ones Eloverblik reported an error for
+    pub fn split(self) -> (Vec<GetMeterReadingsResponseResult>, Vec<ApiError>) {
+        split_results(self.result, |val| val.api_error())
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
+pub struct GetMeterReadingsResponseResult {
+    #[serde(deserialize_with = "null_as_default")]
+    pub result: MeterReadings,
+    pub success: bool,
+    pub error_code: i64,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub error_text: String,
+    pub id: String,
+    pub stack_trace: Value,
+}
+
+impl GetMeterReadingsResponseResult {
+    pub fn api_error(&self) -> Option<ApiError> {
+        api_error(&self.id, self.success, self.error_code, &self.error_text)
+    }
+
+    pub fn into_result(self) -> Result<MeterReadings, ApiError> {
+        match self.api_error() {
+            None => Ok(self.result),
+            Some(err) => Err(err)
+        }
+    }
+}
+
+/// Register readings of the physical meter, as opposed to the consumption per period in [`MyEnergyDataMarketDocument`]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MeterReadings {
+    pub metering_point_id: String,
+    pub readings: Vec<MeterReading>,
+}
+
+impl MeterReadings {
+    /// Merges the readings of another response into this one. Readings of the same meter on the same date are replaced by the ones from `other`.
+    pub fn merge(&mut self, other : MeterReadings) {
+        for reading in other.readings {
+            self.readings.retain(|val| val.meter_number != reading.meter_number || val.reading_date != reading.reading_date);
+            self.readings.push(reading);
+        }
+        // Dates are all ISO 8601 in UTC, so they sort lexicographically
+        self.readings.sort_by(|a, b| a.reading_date.cmp(&b.reading_date));
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MeterReading {
+    pub reading_date: String,
+    pub registration_date: String,
+    pub meter_number: String,
+    pub meter_reading: String,
+    pub measurement_unit: String,
+}
+
+impl MeterReading {
+    pub fn reading_time(&self) -> Option<DateTime<Utc>> {
+        self.reading_date.parse().ok()
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        self.meter_reading.parse().ok()
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct MyEnergyDataMarketDocument {
     #[serde(rename = "mRID")]
     pub m_rid: String,
@@ -167,14 +489,41 @@ pub struct MyEnergyDataMarketDocument {
     pub time_series: Vec<TimeSeries>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+impl MyEnergyDataMarketDocument {
+    /// Merges the time series of another document into this one. Periods covering the same interval are replaced by the ones from `other`.
+    pub fn merge(&mut self, other : MyEnergyDataMarketDocument) {
+        for ts in other.time_series {
+            match self.time_series.iter_mut().find(|existing| existing.market_evaluation_point.m_rid.name == ts.market_evaluation_point.m_rid.name) {
+                None => self.time_series.push(ts),
+                Some(existing) => {
+                    for period in ts.period {
+                        existing.period.retain(|val| val.time_interval.start != period.time_interval.start);
+                        existing.period.push(period);
+                    }
+                    // Timestamps are all ISO 8601 in UTC, so they sort lexicographically
+                    existing.period.sort_by(|a, b| a.time_interval.start.cmp(&b.time_interval.start));
+                }
+            }
+        }
+
+        if other.period_time_interval.start < self.period_time_interval.start {
+            self.period_time_interval.start = other.period_time_interval.start;
+        }
+        if other.period_time_interval.end > self.period_time_interval.end {
+            self.period_time_interval.end = other.period_time_interval.end;
+        }
+        self.created_date_time = other.created_date_time;
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SenderMarketParticipantMRid {
     pub coding_scheme: Value,
     pub name: Value,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PeriodTimeInterval {
     pub start: String,
@@ -219,6 +568,23 @@ pub struct Period {
     pub point: Vec<Point>,
 }
 
+impl Period {
+    /// Start of the interval a point covers, derived from the period start, the resolution and the 1-based position.
+    pub fn point_start(&self, point : &Point) -> Option<DateTime<Utc>> {
+        let start = self.time_interval.start.parse::<DateTime<Utc>>().ok()?;
+        let offset = point.position.parse::<u32>().ok()?.checked_sub(1)?;
+
+        match self.resolution.as_str() {
+            "PT15M" => Some(start + Duration::minutes(15 * offset as i64)),
+            "PT1H" => Some(start + Duration::hours(offset as i64)),
+            "P1D" => Some(start + Duration::days(offset as i64)),
+            "P1M" => start.checked_add_months(Months::new(offset)),
+            "P1Y" => start.checked_add_months(Months::new(12 * offset)),
+            _ => None
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TimeInterval {
@@ -235,3 +601,56 @@ pub struct Point {
     #[serde(rename = "out_Quantity.quality")]
     pub out_quantity_quality: String,
 }
+
+fn api_error(id : &str, success : bool, error_code : i64, error_text : &str) -> Option<ApiError> {
+    if success {
+        return None;
+    }
+
+    Some(ApiError {
+        id: id.to_owned(),
+        code: error_code.into(),
+        text: error_text.to_owned()
+    })
+}
+
+fn split_results<T>(results : Vec<T>, api_error : impl Fn(&T) -> Option<ApiError>) -> (Vec<T>, Vec<ApiError>) {
+    let mut answered = Vec::new();
+    let mut failed = Vec::new();
+    for result in results {
+        match api_error(&result) {
+            None => answered.push(result),
+            Some(err) => failed.push(err)
+        }
+    }
+    (answered, failed)
+}
+
+// Failed results carry `null` instead of the document
+fn null_as_default<'de, D : Deserializer<'de>, T : Default + Deserialize<'de>>(deserializer : D) -> Result<T, D::Error> {
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ApiErrorCode;
+
+    #[test]
+    fn failed_results_are_split_out() {
+        let resp : GetMeteringDataTimeSeriesResponse = serde_json::from_str(r#"{"result": [{
+            "MyEnergyData_MarketDocument": null,
+            "success": false,
+            "errorCode": 30006,
+            "errorText": "AccessToMeteringPointDenied",
+            "id": "571313100000000001",
+            "stackTrace": null
+        }]}"#).unwrap();
+
+        let (answered, failed) = resp.split();
+        assert!(answered.is_empty());
+        assert_eq!(failed[0].id, "571313100000000001");
+        assert_eq!(failed[0].code, ApiErrorCode::AccessToMeteringPointDenied);
+        assert_eq!(ApiErrorCode::from(42), ApiErrorCode::Other(42));
+    }
+}
@@ -0,0 +1,90 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use log::debug;
+
+/// Token bucket shared between every clone of the limiter. Callers wait for a token instead of failing.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    capacity : f64,
+    per : Duration,
+    state : Arc<Mutex<Bucket>>
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens : f64,
+    last_refill : Instant
+}
+
+impl RateLimiter {
+    /// Allows `capacity` calls every `per`, starting with a full bucket
+    pub fn new(capacity : u32, per : Duration) -> Self {
+        Self {
+            capacity: capacity as f64,
+            per,
+            state: Arc::new(Mutex::new(Bucket {
+                tokens: capacity as f64,
+                last_refill: Instant::now()
+            }))
+        }
+    }
+
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.state.lock().unwrap_or_else(|err| err.into_inner());
+                let now = Instant::now();
+                let refill = now.duration_since(bucket.last_refill).as_secs_f64() / self.per.as_secs_f64() * self.capacity;
+                bucket.tokens = (bucket.tokens + refill).min(self.capacity);
+                bucket.last_refill = now;
+
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.capacity * self.per.as_secs_f64())
+            };
+
+            debug!(target:"eloverblik_client::ratelimit", "Quota reached, waiting {:?}", wait);
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Eloverblik's documented quotas, kept separately for the token endpoint and the data endpoints
+#[derive(Clone, Debug)]
+pub struct RateLimits {
+    pub token : RateLimiter,
+    pub data : RateLimiter
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            // Limit to 2 queries every 60 seconds
+            token: RateLimiter::new(2, Duration::from_secs(60)),
+            // Limit most requests to 25 queries every 60 seconds
+            data: RateLimiter::new(25, Duration::from_secs(60))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn acquire_waits_when_empty() {
+        let limiter = RateLimiter::new(2, Duration::from_millis(200));
+        let shared = limiter.clone();
+        let start = Instant::now();
+
+        limiter.acquire().await;
+        shared.acquire().await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        limiter.acquire().await;
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
+}
@@ -0,0 +1,53 @@
+//! In-process mock of the Eloverblik customer API, serving the fixtures in `fixtures/`.
+//! The Energi Data Service client has its own mock, which can be mounted on the same server.
+use wiremock::matchers::{method, path, path_regex};
+use wiremock::{Mock, ResponseTemplate};
+
+pub use wiremock::MockServer;
+
+/// The single metering point every fixture is about
+pub const METERING_POINT_ID : &str = "571313100000000000";
+
+pub const TOKEN_FIXTURE : &str = include_str!("../fixtures/token.json");
+pub const METERING_POINTS_FIXTURE : &str = include_str!("../fixtures/meteringpoints.json");
+pub const CHARGES_FIXTURE : &str = include_str!("../fixtures/getcharges.json");
+pub const TIMESERIES_FIXTURE : &str = include_str!("../fixtures/gettimeseries.json");
+pub const DETAILS_FIXTURE : &str = include_str!("../fixtures/getdetails.json");
+pub const AUTHORIZATIONS_FIXTURE : &str = include_str!("../fixtures/authorizations.json");
+pub const METER_READINGS_FIXTURE : &str = include_str!("../fixtures/getmeterreadings.json");
+pub const TIMESERIES_EXPORT_FIXTURE : &str = include_str!("../fixtures/timeseries_export.csv");
+pub const ADD_RELATION_FIXTURE : &str = include_str!("../fixtures/addrelation.json");
+
+/// Starts a mock answering the token, meteringpoints, getcharges, getdetails, gettimeseries, timeseries export, and getmeterreadings endpoints,
+/// the relation endpoints, plus the third-party authorization endpoints.
+/// The timeseries, its export and the meter readings are returned as-is whatever range is asked for.
+pub async fn start_mock_server() -> MockServer {
+    let server = MockServer::start().await;
+
+    mount_json(&server, "GET", path("/api/token"), TOKEN_FIXTURE).await;
+    mount_json(&server, "GET", path("/api/meteringpoints/meteringpoints"), METERING_POINTS_FIXTURE).await;
+    mount_json(&server, "POST", path("/api/meteringpoints/meteringpoint/getcharges"), CHARGES_FIXTURE).await;
+    mount_json(&server, "POST", path("/api/meteringpoints/meteringpoint/getdetails"), DETAILS_FIXTURE).await;
+    mount_json(&server, "POST", path_regex(r"^/api/meterdata/gettimeseries/[^/]+/[^/]+/[^/]+$"), TIMESERIES_FIXTURE).await;
+    mount_json(&server, "POST", path("/api/meteringpoints/meteringpoint/relation/add"), ADD_RELATION_FIXTURE).await;
+    mount_json(&server, "PUT", path_regex(r"^/api/meteringpoints/meteringpoint/relation/add/[^/]+/[^/]+$"), r#"{"result": "Relation added"}"#).await;
+    mount_json(&server, "DELETE", path_regex(r"^/api/meteringpoints/meteringpoint/relation/[^/]+$"), r#"{"result": true}"#).await;
+    Mock::given(method("POST"))
+        .and(path("/api/meterdata/timeseries/export"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(TIMESERIES_EXPORT_FIXTURE.to_owned(), "text/csv"))
+        .mount(&server)
+        .await;
+    mount_json(&server, "POST", path_regex(r"^/api/meterdata/getmeterreadings/[^/]+/[^/]+$"), METER_READINGS_FIXTURE).await;
+    mount_json(&server, "GET", path("/api/authorization/authorizations"), AUTHORIZATIONS_FIXTURE).await;
+    mount_json(&server, "GET", path_regex(r"^/api/authorization/authorization/meteringpoints/[^/]+/[^/]+$"), METERING_POINTS_FIXTURE).await;
+
+    server
+}
+
+async fn mount_json(server : &MockServer, http_method : &str, matcher : impl wiremock::Match + 'static, body : &str) {
+    Mock::given(method(http_method))
+        .and(matcher)
+        .respond_with(ResponseTemplate::new(200).set_body_raw(body.to_owned(), "application/json"))
+        .mount(server)
+        .await;
+}
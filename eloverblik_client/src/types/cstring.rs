@@ -22,7 +22,7 @@ impl<'de> Deserialize<'de> for CString {
 
 impl From<Vec<u8>> for CString {
     fn from(value: Vec<u8>) -> Self {
-        let val = String::from_utf8(value).unwrap();
+        let val = String::from_utf8_lossy(&value).into_owned();
         Self {
             val
         }
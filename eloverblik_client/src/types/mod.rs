@@ -1 +1,2 @@
-pub mod cstring;
\ No newline at end of file
+pub mod cstring;
+pub mod secret;
@@ -0,0 +1,76 @@
+use std::fmt::{Debug, Display, Formatter};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const REDACTED : &str = "[REDACTED]";
+
+/// A credential such as a refresh or access token. Debug, Display and Serialize all print a placeholder, the value is only reachable through [`Secret::expose`].
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret {
+    val : String
+}
+
+impl Secret {
+    pub fn new(val : impl Into<String>) -> Self {
+        Self {
+            val: val.into()
+        }
+    }
+
+    pub fn expose(&self) -> &str {
+        self.val.as_str()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.val.is_empty()
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret({})", REDACTED)
+    }
+}
+
+impl Display for Secret {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", REDACTED)
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+        Ok(Self::new(String::deserialize(deserializer)?))
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self::new(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_unless_exposed() {
+        let secret = Secret::new("hunter2");
+
+        assert_eq!(format!("{:?} {}", secret, secret), "Secret([REDACTED]) [REDACTED]");
+        assert_eq!(serde_json::to_string(&secret).unwrap(), "\"[REDACTED]\"");
+        assert_eq!(serde_json::from_str::<Secret>("\"hunter2\"").unwrap().expose(), "hunter2");
+    }
+}
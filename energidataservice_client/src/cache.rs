@@ -1,10 +1,12 @@
 use std::collections::{HashMap};
 use std::fmt::{Debug, Formatter};
-use std::io::{Read, Write};
 use base64::Engine;
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use client_common::cache as disk;
+use log::warn;
+use serde::{Deserialize, Serialize};
 
-pub trait Cache<T> {
+// Send and Sync so a client holding a cache can be shared between tasks
+pub trait Cache<T> : Send + Sync {
     fn put(&mut self, key : &str, val : T, expiration_time : Option<i64>);
     fn get(&self, key : &str) -> Option<T>;
     fn has_expired(&self, key : &str) -> bool;
@@ -12,7 +14,7 @@ pub trait Cache<T> {
 
 impl<T> Debug for dyn Cache<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        f.debug_struct("Cache").finish_non_exhaustive()
     }
 }
 
@@ -23,7 +25,7 @@ pub struct InMemoryCache<T> {
     default_expiration_time_in_secs : i64
 }
 
-impl<T : Clone> Cache<T> for InMemoryCache<T> {
+impl<T : Clone + Send + Sync> Cache<T> for InMemoryCache<T> {
     fn put(&mut self, key: &str, val: T, expiration_time : Option<i64>) {
         match expiration_time {
             None => {
@@ -37,16 +39,13 @@ impl<T : Clone> Cache<T> for InMemoryCache<T> {
     }
 
     fn get(&self, key: &str) -> Option<T> {
-        return match self.store.get(key) {
-            None => None,
-            Some(val) => Some(val.clone())
-        }
+        self.store.get(key).cloned()
     }
 
     fn has_expired(&self, key: &str) -> bool {
-        return match self.expiration.get(key) {
+        match self.expiration.get(key) {
             None => true,
-            Some(timestamp) => chrono::Utc::now().timestamp() > timestamp.clone()
+            Some(timestamp) => chrono::Utc::now().timestamp() > *timestamp
         }
     }
 }
@@ -63,7 +62,7 @@ struct DiskCacheStructure<T> {
     pub expires_in : i64,
     pub data : T
 }
-impl<'b, T : AsRef<[u8]> + for<'a> Deserialize<'a> + From<Vec<u8>>> Cache<T> for DiskCache {
+impl<T : AsRef<[u8]> + for<'a> Deserialize<'a> + From<Vec<u8>>> Cache<T> for DiskCache {
     fn put(&mut self, key: &str, val: T, expiration_time: Option<i64>) {
         let expr_time = match expiration_time {
             None => {
@@ -79,46 +78,36 @@ impl<'b, T : AsRef<[u8]> + for<'a> Deserialize<'a> + From<Vec<u8>>> Cache<T> for
             data: base64::engine::general_purpose::STANDARD.encode(val),
         };
 
-        let mut path_buf = std::path::PathBuf::new();
-        path_buf.push(&self.path);
-        std::fs::create_dir_all(path_buf.as_path()).unwrap();
-        path_buf.push(key);
-        let mut file = std::fs::File::create(path_buf.as_path()).unwrap();
-        file.write_all(serde_json::to_vec(&payload).unwrap().as_slice()).unwrap();
+        if let Err(err) = self.write_entry(key, &payload) {
+            warn!(target:"energidataservice_client::cache", "Could not write cache entry '{}': {}", key, err);
+        }
     }
 
     fn get(&self, key: &str) -> Option<T> {
-        let mut path_buf = std::path::PathBuf::new();
-        path_buf.push(&self.path);
-        path_buf.push(key);
-        let mut file = std::fs::File::open(path_buf.as_path());
-
-        return match file {
-            Ok(mut f) => {
-                let mut buf = Vec::new();
-                f.read_to_end(&mut buf).unwrap();
-                let obj : DiskCacheStructure<T> = serde_json::from_slice(&buf).unwrap();
-                let decoded = base64::engine::general_purpose::STANDARD.decode(obj.data).unwrap();
-                Some(decoded.into())
+        let obj : DiskCacheStructure<String> = self.read_entry(key)?;
+        match base64::engine::general_purpose::STANDARD.decode(obj.data) {
+            Ok(decoded) => Some(decoded.into()),
+            Err(err) => {
+                warn!(target:"energidataservice_client::cache", "Ignoring cache entry '{}': {}", key, err);
+                None
             }
-            Err(_) => None
-        };
+        }
     }
 
     fn has_expired(&self, key: &str) -> bool {
-        let mut path_buf = std::path::PathBuf::new();
-        path_buf.push(&self.path);
-        path_buf.push(key);
-        let file = std::fs::File::open(path_buf.as_path());
+        match self.read_entry::<String>(key) {
+            None => true,
+            Some(obj) => chrono::Utc::now().timestamp() > obj.expires_in
+        }
+    }
+}
 
-        return match file {
-            Ok(mut f) => {
-                let mut buf = Vec::new();
-                f.read_to_end(&mut buf).unwrap();
-                let obj : DiskCacheStructure<T> = serde_json::from_slice(&buf).unwrap();
-                chrono::Utc::now().timestamp() > obj.expires_in
-            }
-            Err(_) => true
-        };
+impl DiskCache {
+    fn write_entry<T : Serialize>(&self, key : &str, payload : &DiskCacheStructure<T>) -> std::io::Result<()> {
+        disk::write_entry(&self.path, key, payload)
     }
-}
\ No newline at end of file
+
+    fn read_entry<T : for<'a> Deserialize<'a>>(&self, key : &str) -> Option<DiskCacheStructure<T>> {
+        disk::read_entry(&self.path, key)
+    }
+}
@@ -2,15 +2,19 @@ pub mod error;
 pub mod model;
 pub mod cache;
 pub mod types;
+pub use client_common::retry;
+#[cfg(feature = "test-support")]
+pub mod test_support;
 
 use std::sync::Arc;
 use crossbeam::sync::ShardedLock;
-use reqwest::{Request, Response, Url};
+use reqwest::{Request, Response};
 use serde::{Deserialize, Serialize};
 use error::{Result, Error};
 use crate::cache::{Cache};
 use crate::model::request::{ElSpotPricesRequest};
 use crate::model::response::ElSpotPricesResponse;
+use crate::retry::RetryPolicy;
 use crate::types::cstring::CString;
 
 const BASE_URL : &'static str = "https://api.energidataservice.dk";
@@ -18,8 +22,10 @@ const BASE_URL : &'static str = "https://api.energidataservice.dk";
 #[derive(Clone, Debug)]
 pub struct Client {
     http : reqwest::Client,
+    base_url : String,
     conf : Config,
     cache : Option<Arc<ShardedLock<Box<dyn Cache<CString>>>>>,
+    retry : RetryPolicy,
     data : ClientData
 }
 
@@ -33,32 +39,33 @@ impl Client {
 
     }
 
+    // Sends the request, retrying rate limits and transient errors according to the retry policy
+    async fn send(&self, req : Request) -> Result<Response> {
+        let resp = self.retry.send(req, true, |attempt_req| self.http.execute(attempt_req)).await;
+        self.check_response(resp)
+    }
+
     // Handle rate limits
     fn check_response(&self, resp : std::result::Result<Response, reqwest::Error>) -> Result<Response> {
         match resp {
             Ok(val) => {
-                match val.status().as_u16() {
-                    429 => Err(Error::RateLimited(chrono::Utc::now().timestamp() + 61)),
-                    503 => Err(Error::RateLimited(chrono::Utc::now().timestamp() + 61)),
-                    _ => {
-                        return Ok(val);
-                    }
+                if retry::is_rate_limited(&val) {
+                    Err(Error::RateLimited(retry::retry_after(&val).unwrap_or(chrono::Utc::now().timestamp() + 61)))
+                } else {
+                    Ok(val)
                 }
             },
-            Err(err) => {
-                panic!("{}", err);
-            }
+            Err(err) => Err(err.into())
         }
     }
 
     pub async fn get_elspotprices(&self, params : ElSpotPricesRequest) -> Result<ElSpotPricesResponse> {
-        let mut req = self.http.get(format!("{}/dataset/Elspotprices", BASE_URL).parse::<Url>().unwrap())
+        let mut req = self.http.get(format!("{}/dataset/Elspotprices", self.base_url))
             .query(params.tuples().as_slice())
-            .build().unwrap();
+            .build()?;
         self.prepare_http_request(&mut req).await;
 
-        let resp = self.http.execute(req).await;
-        let checked_resp = self.check_response(resp).unwrap();
+        let checked_resp = self.send(req).await?;
 
         checked_resp.json().await.map_err(|err| err.into())
     }
@@ -79,6 +86,12 @@ impl ClientBuilder {
         self
     }
 
+    /// Overrides where requests go, e.g. to a mock server. Defaults to the public API.
+    pub fn add_base_url(mut self, val : &str) -> ClientBuilder {
+        self.inner.base_url = val.trim_end_matches('/').to_owned();
+        self
+    }
+
     pub fn add_config(mut self, val : Config) -> ClientBuilder {
         self.inner.conf = val;
         self
@@ -88,6 +101,11 @@ impl ClientBuilder {
         self.inner.cache = Some(Arc::new(ShardedLock::new(val)));
         self
     }
+
+    pub fn add_retry_policy(mut self, val : RetryPolicy) -> ClientBuilder {
+        self.inner.retry = val;
+        self
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
@@ -97,8 +115,10 @@ pub struct Config {
 pub fn new_default_client(conf : Config) -> Client {
     Client {
         http: reqwest::Client::default(),
+        base_url: BASE_URL.to_owned(),
         conf,
         cache: None,
+        retry: RetryPolicy::default(),
         data: ClientData {
         }
     }
@@ -120,4 +140,26 @@ mod tests {
         // let result = add(2, 2);
         // assert_eq!(result, 4);
     }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    async fn get_elspotprices_from_mock() {
+        let server = test_support::start_mock_server().await;
+        let client = new_builder()
+            .add_base_url(&server.uri())
+            .add_retry_policy(RetryPolicy::none())
+            .build();
+
+        let resp = client.get_elspotprices(ElSpotPricesRequest {
+            limit: None,
+            timezone: None,
+            start: Some("2023-08-02T00:00".to_owned()),
+            end: Some("2023-08-03T00:00".to_owned()),
+            filter: Some(r#"{"PriceArea":["DK2"]}"#.to_owned()),
+            sort: None,
+        }).await.unwrap();
+
+        assert_eq!(resp.records.len(), 2);
+        assert_eq!(resp.records[0].price_area, "DK2");
+    }
 }
@@ -0,0 +1,24 @@
+//! In-process mock of Energi Data Service's Elspotprices dataset, serving the fixtures in `fixtures/`.
+//! Can be mounted next to other mocks on one server, as it only answers `/dataset/` paths.
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+pub use wiremock::MockServer;
+
+pub const ELSPOTPRICES_FIXTURE : &str = include_str!("../fixtures/elspotprices.json");
+
+/// Starts a mock answering the Elspotprices dataset, see [`mount`]
+pub async fn start_mock_server() -> MockServer {
+    let server = MockServer::start().await;
+    mount(&server).await;
+    server
+}
+
+/// Answers the Elspotprices dataset on `server`. The prices are returned as-is whatever range or area is asked for.
+pub async fn mount(server : &MockServer) {
+    Mock::given(method("GET"))
+        .and(path("/dataset/Elspotprices"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(ELSPOTPRICES_FIXTURE.to_owned(), "application/json"))
+        .mount(server)
+        .await;
+}
@@ -0,0 +1,5 @@
+DROP TABLE documents;
+DROP TABLE charges;
+DROP TABLE spot_prices;
+DROP TABLE readings;
+DROP TABLE metering_points;
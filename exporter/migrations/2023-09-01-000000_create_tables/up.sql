@@ -0,0 +1,51 @@
+CREATE TABLE metering_points (
+    metering_point_id TEXT PRIMARY KEY,
+    type_of_mp TEXT NOT NULL,
+    settlement_method TEXT NOT NULL,
+    meter_number TEXT NOT NULL,
+    postcode TEXT NOT NULL,
+    city_name TEXT NOT NULL,
+    balance_supplier_name TEXT NOT NULL,
+    data JSONB NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE readings (
+    metering_point_id TEXT NOT NULL,
+    reading_time TIMESTAMPTZ NOT NULL,
+    resolution TEXT NOT NULL,
+    quantity DOUBLE PRECISION NOT NULL,
+    quality TEXT NOT NULL,
+    unit TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (metering_point_id, reading_time)
+);
+
+CREATE TABLE spot_prices (
+    price_area TEXT NOT NULL,
+    hour_utc TIMESTAMPTZ NOT NULL,
+    spot_price_dkk DOUBLE PRECISION NOT NULL,
+    spot_price_eur DOUBLE PRECISION NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (price_area, hour_utc)
+);
+
+CREATE TABLE charges (
+    metering_point_id TEXT NOT NULL,
+    charge_type TEXT NOT NULL,
+    name TEXT NOT NULL,
+    owner TEXT NOT NULL,
+    valid_from_date TEXT NOT NULL,
+    position TEXT NOT NULL,
+    price DOUBLE PRECISION NOT NULL,
+    description TEXT NOT NULL,
+    period_type TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (metering_point_id, charge_type, name, owner, valid_from_date, position)
+);
+
+CREATE TABLE documents (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,4 @@
+DELETE FROM documents WHERE kind <> 'string';
+ALTER TABLE documents DROP CONSTRAINT documents_pkey;
+ALTER TABLE documents DROP COLUMN kind;
+ALTER TABLE documents ADD PRIMARY KEY (key);
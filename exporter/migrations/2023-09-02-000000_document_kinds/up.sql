@@ -0,0 +1,3 @@
+ALTER TABLE documents ADD COLUMN kind TEXT NOT NULL DEFAULT 'string';
+ALTER TABLE documents DROP CONSTRAINT documents_pkey;
+ALTER TABLE documents ADD PRIMARY KEY (kind, key);
@@ -0,0 +1,10 @@
+CREATE TABLE meter_readings (
+    metering_point_id TEXT NOT NULL,
+    meter_number TEXT NOT NULL,
+    reading_time TIMESTAMPTZ NOT NULL,
+    registration_date TEXT NOT NULL,
+    meter_reading DOUBLE PRECISION NOT NULL,
+    unit TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (metering_point_id, meter_number, reading_time)
+);
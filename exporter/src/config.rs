@@ -1,7 +1,10 @@
-use anyhow::anyhow;
-use config::{ConfigBuilder, ConfigError};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use config::ConfigBuilder;
 use config::builder::DefaultState;
+use eloverblik_client::types::secret::Secret;
 use serde::{Serialize, Deserialize};
+use tracing::warn;
 use crate::error::Result;
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
@@ -11,7 +14,45 @@ pub struct Config {
     pub api_listen_address : String,
     pub metrics_port : u16,
     pub metrics_listen_address : String,
-    pub eloverblik_refresh_token : String,
+    #[serde(default)]
+    pub eloverblik_refresh_token : Secret,
+    /// Reads `eloverblik_refresh_token` from this file instead, re-reading it when it changes
+    pub eloverblik_refresh_token_file : Option<String>,
+    pub price_area : String,
+    pub mode : RunMode,
+    pub sync_schedule : String,
+    pub postgres_url : Option<Secret>,
+    /// Reads `postgres_url` from this file instead
+    pub postgres_url_file : Option<String>,
+    /// Days fetched for a metering point that has not been synced before
+    pub initial_backfill_days : i64,
+    /// First day fetched in backfill mode, as YYYY-MM-DD
+    pub backfill_from : Option<String>,
+    /// Day after the last one fetched in backfill mode, as YYYY-MM-DD. Defaults to today.
+    pub backfill_to : Option<String>,
+    /// Days fetched per timeseries request, at most 730
+    pub backfill_chunk_days : i64,
+    /// CSV exports from Eloverblik, e.g. downloaded from the portal, stored in import mode
+    #[serde(default)]
+    pub import_files : Vec<String>,
+    /// Overrides the Eloverblik customer API location, e.g. to run against a mock
+    pub eloverblik_base_url : Option<String>,
+    /// Overrides the Energi Data Service API location
+    pub energidataservice_base_url : Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RunMode {
+    /// Sync once and keep serving metrics
+    Once,
+    /// Sync on `sync_schedule` until stopped
+    #[default]
+    Daemon,
+    /// Fetch `backfill_from` to `backfill_to` and exit
+    Backfill,
+    /// Store `import_files` and exit
+    Import,
 }
 
 pub fn get_conf_path() -> String {
@@ -19,26 +60,153 @@ pub fn get_conf_path() -> String {
 }
 
 pub fn load_conf() -> Result<Config> {
-    let mut settings = config::Config::builder()
+    let settings = config::Config::builder()
         .add_source(config::Environment::with_prefix("ELOVERBLIK_EXPORTER").separator("__"))
         .add_source(config::File::with_name(format!("{}/{}", get_conf_path(), "config.yaml").as_str()).required(false));
-    settings = set_defaults(settings);
-    let settings_built = settings
+
+    build_conf(settings)
+}
+
+fn build_conf(settings : ConfigBuilder<DefaultState>) -> Result<Config> {
+    let settings_built = set_defaults(settings)
         .build()
         .unwrap();
 
-    let config : Config = settings_built.try_deserialize()?;
+    let config : Config = resolve_files(settings_built)?.try_deserialize()?;
+
+    if config.eloverblik_refresh_token.is_empty() {
+        return Err(config::ConfigError::Message("eloverblik_refresh_token or eloverblik_refresh_token_file must be set".to_owned()).into());
+    }
 
     Ok(config)
 }
 
+/// Credentials that can be given as `<name>_file` instead, pointing at e.g. a Kubernetes or Docker secret mount
+const SECRET_FILE_KEYS : &[&str] = &["eloverblik_refresh_token", "postgres_url"];
+
+// The file wins over a value given directly
+fn resolve_files(settings : config::Config) -> Result<config::Config> {
+    let mut builder = config::Config::builder().add_source(settings.clone());
+    for key in SECRET_FILE_KEYS {
+        if let Ok(path) = settings.get_string(&format!("{}_file", key)) {
+            builder = builder.set_override(*key, read_secret_file(&path)?.expose().to_owned())?;
+        }
+    }
+
+    Ok(builder.build()?)
+}
+
 fn set_defaults(builder : ConfigBuilder<DefaultState>) -> ConfigBuilder<DefaultState> {
-    let builder = builder
+    builder
         .set_default("api_port", 8080).unwrap()
         .set_default("api_listen_address", "0.0.0.0").unwrap()
         .set_default("metrics_port", 9000).unwrap()
         .set_default("metrics_listen_address", "0.0.0.0").unwrap()
-        .set_default("log_level", "info").unwrap();
+        .set_default("log_level", "info").unwrap()
+        .set_default("price_area", "DK2").unwrap()
+        .set_default("mode", "daemon").unwrap()
+        // sec min hour day_of_month month day_of_week
+        .set_default("sync_schedule", "0 0 * * * *").unwrap()
+        .set_default("initial_backfill_days", 31).unwrap()
+        .set_default("backfill_chunk_days", 90).unwrap()
+}
 
-    builder
-}
\ No newline at end of file
+fn read_secret_file(path : &str) -> Result<Secret> {
+    Ok(Secret::new(std::fs::read_to_string(path)?.trim()))
+}
+
+fn modified(path : &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|val| val.modified()).ok()
+}
+
+/// A credential kept in a file, remembering when it was last read so a rotated value can be picked up
+#[derive(Debug)]
+pub struct SecretFile {
+    pub path : String,
+    modified : Mutex<Option<SystemTime>>
+}
+
+impl SecretFile {
+    /// Starts from the file as it is now, as `load_conf` has already read it
+    pub fn new(path : &str) -> Self {
+        Self {
+            path: path.to_owned(),
+            modified: Mutex::new(modified(path))
+        }
+    }
+
+    /// The contents of the file if it has been modified since it was last read.
+    /// A file that is missing or empty, e.g. halfway through a rotation, is skipped and read again next time.
+    pub fn read_if_changed(&self) -> Option<Secret> {
+        let current = modified(&self.path);
+        let mut last = self.modified.lock().unwrap_or_else(|err| err.into_inner());
+        if current.is_some() && current == *last {
+            return None;
+        }
+
+        match read_secret_file(&self.path) {
+            Ok(val) if !val.is_empty() => {
+                *last = current;
+                Some(val)
+            }
+            Ok(_) => {
+                warn!("{} is empty, keeping the previous value", self.path);
+                None
+            }
+            Err(err) => {
+                warn!("Could not read {}, keeping the previous value: {}", self.path, err);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn secret_file_is_reread_when_modified() {
+        let path = std::env::temp_dir().join(format!("eloverblik-secret-test-{}", std::process::id()));
+        let path_str = path.to_string_lossy().into_owned();
+        std::fs::write(&path, "first\n").unwrap();
+
+        let file = SecretFile::new(&path_str);
+        assert!(file.read_if_changed().is_none());
+
+        std::fs::write(&path, "second\n").unwrap();
+        std::fs::File::options().write(true).open(&path).unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+        assert_eq!(file.read_if_changed().unwrap().expose(), "second");
+        assert!(file.read_if_changed().is_none());
+
+        // A rotation that removes the file first does not fail, and the new file is picked up once it is there
+        std::fs::remove_file(&path).unwrap();
+        assert!(file.read_if_changed().is_none());
+        std::fs::write(&path, "third\n").unwrap();
+        assert_eq!(file.read_if_changed().unwrap().expose(), "third");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn refresh_token_can_be_given_as_file_only() {
+        let path = std::env::temp_dir().join(format!("eloverblik-token-file-test-{}", std::process::id()));
+        std::fs::write(&path, "from-file\n").unwrap();
+
+        let yaml = format!("eloverblik_refresh_token_file: {}", path.to_string_lossy());
+        let conf = build_conf(config::Config::builder().add_source(config::File::from_str(&yaml, config::FileFormat::Yaml))).unwrap();
+        assert_eq!(conf.eloverblik_refresh_token.expose(), "from-file");
+
+        // Other credentials resolve the same way, settings that are not credentials are left alone
+        let yaml = format!("eloverblik_refresh_token: direct\npostgres_url_file: {0}\nprice_area_file: {0}", path.to_string_lossy());
+        let conf = build_conf(config::Config::builder().add_source(config::File::from_str(&yaml, config::FileFormat::Yaml))).unwrap();
+        assert_eq!(conf.postgres_url.as_ref().map(|val| val.expose()), Some("from-file"));
+        assert_eq!(conf.price_area, "DK2");
+
+        assert!(build_conf(config::Config::builder()).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
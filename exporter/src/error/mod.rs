@@ -8,10 +8,26 @@ pub enum Error {
     RequestError(Box<dyn std::error::Error + Send>),
     #[error("serde_json error")]
     SerdeJsonError(Box<dyn std::error::Error + Send>),
-    #[error("eloverblik_client error")]
+    #[error("eloverblik_client error: {0}")]
     ElOverblikClientError(Box<dyn std::error::Error + Send>),
+    #[error("energidataservice_client error")]
+    EnergiDataServiceClientError(Box<dyn std::error::Error + Send>),
     #[error("config error")]
     ConfigError(Box<dyn std::error::Error + Send>),
+    #[error("metrics error")]
+    MetricsError(Box<dyn std::error::Error + Send>),
+    #[error("io error")]
+    IoError(Box<dyn std::error::Error + Send>),
+    #[error("database error")]
+    DatabaseError(Box<dyn std::error::Error + Send>),
+    #[error("lock poisoned")]
+    LockPoisoned,
+    #[error("missing data: {0}")]
+    MissingData(String),
+    #[error("cannot import: {0}")]
+    InvalidImport(String),
+    #[error("Eloverblik refresh token expired at {0}, create a new one on eloverblik.dk and update eloverblik_refresh_token")]
+    RefreshTokenExpired(chrono::DateTime<chrono::Utc>),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -36,6 +52,39 @@ impl From<config::ConfigError> for Error {
 
 impl From<eloverblik_client::error::Error> for Error {
     fn from(value: eloverblik_client::error::Error) -> Self {
-        Self::ElOverblikClientError(Box::new(value))
+        match value {
+            eloverblik_client::error::Error::RefreshTokenExpired(expiry) => Self::RefreshTokenExpired(expiry),
+            value => Self::ElOverblikClientError(Box::new(value))
+        }
+    }
+}
+
+impl From<prometheus::Error> for Error {
+    fn from(value: prometheus::Error) -> Self {
+        Self::MetricsError(Box::new(value))
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(Box::new(value))
+    }
+}
+
+impl From<energidataservice_client::error::Error> for Error {
+    fn from(value: energidataservice_client::error::Error) -> Self {
+        Self::EnergiDataServiceClientError(Box::new(value))
+    }
+}
+
+impl From<diesel::result::Error> for Error {
+    fn from(value: diesel::result::Error) -> Self {
+        Self::DatabaseError(Box::new(value))
+    }
+}
+
+impl From<diesel::ConnectionError> for Error {
+    fn from(value: diesel::ConnectionError) -> Self {
+        Self::DatabaseError(Box::new(value))
     }
 }
\ No newline at end of file
@@ -1,78 +1,117 @@
 use eloverblik_client::cache::DiskCache;
-use eloverblik_client::model::request::{GetMeteringDataTimeSeriesRequest, GetMeteringPointChargesRequest, MeteringPoints};
-use energidataservice_client::model::request::ElSpotPricesRequest;
-use crate::model::UsageTimeSeries;
+use tracing::{error, info};
+use crate::config::{RunMode, SecretFile};
+use crate::metrics::Metrics;
 use crate::store::fs::FsStore;
-use crate::store::{Store, StoreType};
+use crate::store::postgres::PostgresStore;
+use crate::store::Store;
+use crate::sync::Syncer;
 
 mod config;
 mod error;
 mod store;
 mod model;
+mod metrics;
+mod sync;
 
 #[tokio::main]
 async fn main() {
     let conf = config::load_conf().unwrap();
     setup_tracing(get_trace_level(&conf.log_level));
-    let client = eloverblik_client::new_builder()
+    let metrics = Metrics::new().unwrap();
+    let metrics_server = metrics::serve(metrics.clone(), &conf.metrics_listen_address, conf.metrics_port).unwrap();
+    let metrics_server = tokio::spawn(metrics_server);
+
+    let mut client = eloverblik_client::new_builder()
         .add_config(eloverblik_client::Config {
-            refresh_token: conf.eloverblik_refresh_token
+            refresh_token: conf.eloverblik_refresh_token.clone()
         })
-        .add_cache(Box::new(DiskCache {
-            path: "eloverblik-cache".to_owned(),
-            default_expiration_time_in_secs: 3600
-        }))
-        .build();
-
-    let eds_client = energidataservice_client::new_builder()
-        .build();
-
-    let metering_points = client.get_metering_points().await.unwrap();
-    let first_meter_point = metering_points.result.last().unwrap();
-
-    let timeseries = client.get_metering_data_timeseries(GetMeteringDataTimeSeriesRequest {
-        metering_points: MeteringPoints {
-            metering_point: vec![first_meter_point.metering_point_id.clone()]
-        }
-    }, "2023-08-01", "2023-08-31", "Hour").await.unwrap();
+        .add_cache(Box::new(DiskCache::new("eloverblik-cache", 3600)));
+    if let Some(url) = &conf.eloverblik_base_url {
+        client = client.add_base_url(url);
+    }
+    let client = client.build();
 
-    let metering_point_charges = client.get_metering_point_charges(GetMeteringPointChargesRequest {
-        metering_points: MeteringPoints {
-            metering_point: vec![first_meter_point.metering_point_id.clone()]
-        }
-    }).await.unwrap();
-    let first_meter_point_charges = metering_point_charges.result.last().unwrap();
+    let mut eds_client = energidataservice_client::new_builder();
+    if let Some(url) = &conf.energidataservice_base_url {
+        eds_client = eds_client.add_base_url(url);
+    }
+    let eds_client = eds_client.build();
 
     let mut stores : Vec<Box<dyn Store>> = Vec::new();
     stores.push(Box::new(FsStore {
         path: "eloverblik-store".to_owned()
     }));
+    if let Some(url) = &conf.postgres_url {
+        stores.push(Box::new(PostgresStore::connect(url.expose()).unwrap()));
+    }
 
-    let prices = eds_client.get_elspotprices(ElSpotPricesRequest {
-        limit: Some(0),
-        timezone: Some("UTC".to_owned()),
-        start: Some("2023-08-01".to_owned()),
-        end: Some("2023-08-31".to_owned()),
-        filter: Some("{\"PriceArea\":[\"DK2\"]}".to_owned()),
-        sort: Some("HourUTC".to_owned()),
-    }).await.unwrap();
-
-    let prices_map = prices.clone().into_records_as_map();
-
-
-    let hourly = UsageTimeSeries::new_hourly(timeseries.result.last().unwrap().clone(), &prices_map, &first_meter_point_charges);
-    let daily = UsageTimeSeries::new_daily(timeseries.result.last().unwrap().clone(), &prices_map, &first_meter_point_charges);
+    let syncer = Syncer {
+        conf: conf.clone(),
+        client,
+        eds_client,
+        stores,
+        metrics,
+        refresh_token_file: conf.eloverblik_refresh_token_file.as_deref().map(SecretFile::new),
+    };
+
+    match conf.mode {
+        RunMode::Once => {
+            syncer.run().await.unwrap();
+        }
+        RunMode::Daemon => {
+            let schedule = conf.sync_schedule.parse::<job_scheduler_ng::Schedule>().unwrap();
+            let (tx, rx) = flume::bounded::<()>(1);
+            std::thread::spawn(move || run_scheduler(schedule, tx));
+
+            // Sync right away instead of waiting for the first scheduled run
+            if let Err(err) = syncer.run().await {
+                error!("Sync failed: {:?}", err);
+            }
+
+            while rx.recv_async().await.is_ok() {
+                if let Err(err) = syncer.run().await {
+                    error!("Sync failed: {:?}", err);
+                }
+            }
+        }
+        RunMode::Backfill => {
+            let from = conf.backfill_from.as_ref().expect("backfill_from is required in backfill mode");
+            let from = chrono::NaiveDate::parse_from_str(from, "%Y-%m-%d").unwrap();
+            let to = match &conf.backfill_to {
+                None => chrono::Utc::now().date_naive(),
+                Some(val) => chrono::NaiveDate::parse_from_str(val, "%Y-%m-%d").unwrap()
+            };
+            syncer.backfill(from, to).await.unwrap();
+            info!("Backfilled {} to {}", from, to);
+            return;
+        }
+        RunMode::Import => {
+            for path in &conf.import_files {
+                let content = std::fs::read_to_string(path).unwrap();
+                let document = eloverblik_client::export::parse_timeseries_csv(&content).unwrap();
+                syncer.import(document).await.unwrap();
+                info!("Imported {}", path);
+            }
+            return;
+        }
+    }
 
+    metrics_server.await.unwrap().unwrap();
+}
 
+fn run_scheduler(schedule : job_scheduler_ng::Schedule, tx : flume::Sender<()>) {
+    info!("Scheduling syncs with '{}'", schedule);
+    let mut scheduler = job_scheduler_ng::JobScheduler::new();
+    scheduler.add(job_scheduler_ng::Job::new(schedule, move || {
+        // A full channel means a sync is already queued, so dropping this run is fine
+        let _ = tx.try_send(());
+    }));
 
-    for store in &stores {
-        store.put(StoreType::MeterDataTimeSeries(timeseries.result.last().unwrap().clone())).unwrap();
-        store.put(StoreType::UsageTimeSeries {key: "hourly".to_owned(), value: hourly.clone()}).unwrap();
-        store.put(StoreType::UsageTimeSeries {key: "daily".to_owned(), value: daily.clone()}).unwrap();
-        store.put(StoreType::String {key: "prices".to_owned(), value: serde_json::to_string(&prices).unwrap()}).unwrap();
-        store.put(StoreType::String {key: "meteringpoint_charges.json".to_owned(), value: serde_json::to_string(&metering_point_charges).unwrap()}).unwrap();
+    loop {
+        scheduler.tick();
+        std::thread::sleep(scheduler.time_till_next_job());
     }
-
 }
 
 
@@ -0,0 +1,225 @@
+use std::sync::{Arc, Mutex};
+use actix_web::{get, web, App, HttpResponse, HttpServer, Responder};
+use eloverblik_client::cache::CacheStats;
+use eloverblik_client::model::response::MeteringPointDetails;
+use prometheus::{Encoder, Gauge, GaugeVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+use tracing::info;
+use crate::error::Result;
+use crate::model::UsageTimeSeries;
+
+const LABELS : &[&str] = &["metering_point_id", "price_area", "type_of_mp"];
+const DETAIL_LABELS : &[&str] = &[
+    "grid_operator", "metering_grid_area", "estimated_annual_consumption_kwh", "connection_capacity_kw",
+    "net_settlement_group", "settlement_method", "physical_status", "balance_supplier", "balance_supplier_start_date"
+];
+
+#[derive(Clone)]
+pub struct Metrics {
+    registry : Registry,
+    consumption_kwh : GaugeVec,
+    cost : GaugeVec,
+    spot_price : GaugeVec,
+    tariff : GaugeVec,
+    reading_timestamp : GaugeVec,
+    consumption_kwh_daily : GaugeVec,
+    cost_daily : GaugeVec,
+    syncs : IntCounter,
+    api_errors : IntCounterVec,
+    skipped_fetches : IntCounterVec,
+    refresh_token_expiry_days : Gauge,
+    token_cache_lookups : IntCounterVec,
+    // The cache counts since it was created, the counter only takes what is new since the last sync
+    last_cache_stats : Arc<Mutex<CacheStats>>,
+    metering_point_info : IntGaugeVec,
+}
+
+/// Labels identifying a single metering point on every published series.
+#[derive(Clone, Debug)]
+pub struct MeteringPointLabels {
+    pub metering_point_id : String,
+    pub price_area : String,
+    pub type_of_mp : String,
+}
+
+impl MeteringPointLabels {
+    fn values(&self) -> [&str; 3] {
+        [self.metering_point_id.as_str(), self.price_area.as_str(), self.type_of_mp.as_str()]
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new_custom(Some("eloverblik".to_owned()), None)?;
+
+        let consumption_kwh = GaugeVec::new(Opts::new("consumption_kwh", "Consumption in the latest available hour"), LABELS)?;
+        let cost = GaugeVec::new(Opts::new("cost", "Cost of the consumption in the latest available hour"), LABELS)?;
+        let spot_price = GaugeVec::new(Opts::new("spot_price_kwh", "Spot price per kWh in the latest available hour"), LABELS)?;
+        let tariff = GaugeVec::new(Opts::new("tariff_kwh", "Tariff per kWh in the latest available hour"), &[LABELS, &["tariff"]].concat())?;
+        let reading_timestamp = GaugeVec::new(Opts::new("latest_reading_timestamp_seconds", "Start of the latest available hour as a unix timestamp"), LABELS)?;
+        let consumption_kwh_daily = GaugeVec::new(Opts::new("consumption_kwh_daily", "Consumption in the latest available day"), LABELS)?;
+        let cost_daily = GaugeVec::new(Opts::new("cost_daily", "Cost of the consumption in the latest available day"), LABELS)?;
+        let syncs = IntCounter::new("syncs_total", "Number of completed syncs")?;
+        let refresh_token_expiry_days = Gauge::new("refresh_token_expiry_days", "Days until the Eloverblik refresh token expires, negative once it has")?;
+        let token_cache_lookups = IntCounterVec::new(Opts::new("token_cache_lookups_total", "Access token cache lookups, by result"), &["result"])?;
+        let metering_point_info = IntGaugeVec::new(Opts::new("metering_point_info", "Details of a metering point as labels, always 1"), &[LABELS, DETAIL_LABELS].concat())?;
+        let api_errors = IntCounterVec::new(Opts::new("api_errors_total", "Metering points Eloverblik returned an error for"), &["metering_point_id", "error_code"])?;
+        let skipped_fetches = IntCounterVec::new(Opts::new("skipped_fetches_total", "Failures fetching or storing optional data, which the sync went on without"), &["kind"])?;
+
+        registry.register(Box::new(consumption_kwh.clone()))?;
+        registry.register(Box::new(cost.clone()))?;
+        registry.register(Box::new(spot_price.clone()))?;
+        registry.register(Box::new(tariff.clone()))?;
+        registry.register(Box::new(reading_timestamp.clone()))?;
+        registry.register(Box::new(consumption_kwh_daily.clone()))?;
+        registry.register(Box::new(cost_daily.clone()))?;
+        registry.register(Box::new(syncs.clone()))?;
+        registry.register(Box::new(api_errors.clone()))?;
+        registry.register(Box::new(skipped_fetches.clone()))?;
+        registry.register(Box::new(refresh_token_expiry_days.clone()))?;
+        registry.register(Box::new(token_cache_lookups.clone()))?;
+        registry.register(Box::new(metering_point_info.clone()))?;
+
+        Ok(Self {
+            registry,
+            consumption_kwh,
+            cost,
+            spot_price,
+            tariff,
+            reading_timestamp,
+            consumption_kwh_daily,
+            cost_daily,
+            syncs,
+            api_errors,
+            skipped_fetches,
+            refresh_token_expiry_days,
+            token_cache_lookups,
+            last_cache_stats: Arc::new(Mutex::new(CacheStats::default())),
+            metering_point_info,
+        })
+    }
+
+    pub fn update(&self, labels : &MeteringPointLabels, hourly : &UsageTimeSeries, daily : &UsageTimeSeries) {
+        let values = labels.values();
+
+        if let Some((time, data)) = hourly.latest() {
+            self.consumption_kwh.with_label_values(&values).set(data.wh);
+            self.cost.with_label_values(&values).set(data.cost);
+            self.spot_price.with_label_values(&values).set(data.spot_price);
+            self.reading_timestamp.with_label_values(&values).set(time.and_utc().timestamp() as f64);
+            for (name, price) in &data.tariffs {
+                self.tariff.with_label_values(&[&values[..], &[name.as_str()]].concat()).set(*price);
+            }
+        }
+
+        if let Some((_, data)) = daily.latest() {
+            self.consumption_kwh_daily.with_label_values(&values).set(data.wh);
+            self.cost_daily.with_label_values(&values).set(data.cost);
+        }
+    }
+
+    pub fn inc_syncs(&self) {
+        self.syncs.inc();
+    }
+
+    pub fn inc_api_errors(&self, metering_point_id : &str, error_code : i64) {
+        self.api_errors.with_label_values(&[metering_point_id, &error_code.to_string()]).inc();
+    }
+
+    pub fn inc_skipped_fetches(&self, kind : &str) {
+        self.skipped_fetches.with_label_values(&[kind]).inc();
+    }
+
+    pub fn set_refresh_token_expiry(&self, expiry : chrono::DateTime<chrono::Utc>) {
+        self.refresh_token_expiry_days.set((expiry - chrono::Utc::now()).num_seconds() as f64 / 86400.0);
+    }
+
+    /// Replaces the published details of every metering point, so changed details do not linger as a second series
+    pub fn set_details(&self, details : &[(MeteringPointLabels, MeteringPointDetails)]) {
+        self.metering_point_info.reset();
+        for (labels, val) in details {
+            let detail_values = [
+                &val.grid_operator_name, &val.metering_grid_area_identification, &val.estimated_annual_volume, &val.mp_capacity,
+                &val.net_settlement_group, &val.settlement_method, &val.physical_status_of_mp, &val.balance_supplier_name, &val.balance_supplier_start_date
+            ].map(|field| field.as_deref().unwrap_or_default());
+            self.metering_point_info.with_label_values(&[&labels.values()[..], &detail_values[..]].concat()).set(1);
+        }
+    }
+
+    pub fn record_cache_stats(&self, stats : &CacheStats) {
+        let mut last = self.last_cache_stats.lock().unwrap_or_else(|err| err.into_inner());
+        // Counts going down mean the cache was recreated, so everything it holds is new
+        let delta = |current : u64, previous : u64| if current < previous { current } else { current - previous };
+        self.token_cache_lookups.with_label_values(&["hit"]).inc_by(delta(stats.hits, last.hits));
+        self.token_cache_lookups.with_label_values(&["miss"]).inc_by(delta(stats.misses, last.misses));
+        *last = *stats;
+    }
+
+    pub fn encode(&self) -> Result<String> {
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+}
+
+#[get("/metrics")]
+async fn metrics_handler(metrics : web::Data<Metrics>) -> impl Responder {
+    match metrics.encode() {
+        Ok(body) => HttpResponse::Ok().content_type(prometheus::TEXT_FORMAT).body(body),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string())
+    }
+}
+
+pub fn serve(metrics : Metrics, listen_address : &str, port : u16) -> Result<actix_web::dev::Server> {
+    info!("Serving metrics on {}:{}", listen_address, port);
+    let data = web::Data::new(metrics);
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(data.clone())
+            .service(metrics_handler)
+    })
+        .workers(1)
+        .bind((listen_address, port))?
+        .run();
+
+    Ok(server)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use super::*;
+    use crate::model::{Data, Granularity};
+
+    #[test]
+    fn update_publishes_latest_hour() {
+        let metrics = Metrics::new().unwrap();
+        let labels = MeteringPointLabels {
+            metering_point_id: "571313100000000000".to_owned(),
+            price_area: "DK2".to_owned(),
+            type_of_mp: "E17".to_owned(),
+        };
+
+        let mut hourly = UsageTimeSeries::new(Granularity::Hourly);
+        hourly.data.insert("12/31/2022 23:00".to_owned(), Data { wh: 1.0, cost: 1.0, spot_price: 0.1, tariffs: BTreeMap::new() });
+        hourly.data.insert("01/01/2023 00:00".to_owned(), Data { wh: 2.0, cost: 3.0, spot_price: 0.2, tariffs: BTreeMap::from([("Elafgift".to_owned(), 0.7)]) });
+        let daily = UsageTimeSeries::new(Granularity::Daily);
+
+        metrics.update(&labels, &hourly, &daily);
+        let encoded = metrics.encode().unwrap();
+
+        assert!(encoded.contains("eloverblik_consumption_kwh{metering_point_id=\"571313100000000000\",price_area=\"DK2\",type_of_mp=\"E17\"} 2"));
+        assert!(encoded.contains("tariff=\"Elafgift\""));
+    }
+
+    #[test]
+    fn cache_lookups_count_up_by_what_is_new() {
+        let metrics = Metrics::new().unwrap();
+
+        metrics.record_cache_stats(&CacheStats { hits: 2, misses: 1 });
+        metrics.record_cache_stats(&CacheStats { hits: 5, misses: 1 });
+        let encoded = metrics.encode().unwrap();
+
+        assert!(encoded.contains("eloverblik_token_cache_lookups_total{result=\"hit\"} 5"));
+        assert!(encoded.contains("eloverblik_token_cache_lookups_total{result=\"miss\"} 1"));
+    }
+}
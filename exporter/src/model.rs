@@ -12,7 +12,11 @@ pub struct UsageTimeSeries {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Data {
     pub wh : f64,
-    pub cost : f64
+    pub cost : f64,
+    #[serde(default)]
+    pub spot_price : f64,
+    #[serde(default)]
+    pub tariffs : BTreeMap<String, f64>
 }
 
 impl UsageTimeSeries {
@@ -23,10 +27,13 @@ impl UsageTimeSeries {
         }
     }
 
-    pub fn new_hourly(source : GetMeteringDataTimeSeriesResponseResult, prices : &HashMap<String, Record>, meter_point_charges : &GetMeteringPointChargesResponseResult) -> Self {
+    pub fn new_hourly(source : GetMeteringDataTimeSeriesResponseResult, prices : &HashMap<String, Record>, meter_point_charges : Option<&GetMeteringPointChargesResponseResult>) -> Self {
         let mut payload = Self::new(Granularity::Hourly);
 
-        let ts = source.my_energy_data_market_document.time_series.last().unwrap();
+        let ts = match source.my_energy_data_market_document.time_series.last() {
+            None => return payload,
+            Some(val) => val
+        };
 
         for period in &ts.period {
             let parsed_date = period.time_interval.end.clone().parse::<chrono::DateTime<chrono::Utc>>().unwrap();
@@ -41,31 +48,40 @@ impl UsageTimeSeries {
                     }
                 };
                 let key = format!("{} {}", base_key, pos);
+                let spot_price = prices.get(&key).map(|val| val.as_kwh_price_eur()).unwrap_or(0.0);
                 let price = match prices.get(&key) {
                     None => 0.0,
                     Some(val) => {
                         if point.out_quantity_quantity.eq("0.0") {
                             0.0
                         } else {
-                            meter_point_charges.result.get_full_price(val.as_kwh_price_eur(), point.position.clone(), point.out_quantity_quantity.parse().unwrap())
+                            match meter_point_charges {
+                                None => val.as_kwh_price_eur(),
+                                Some(charges) => charges.result.get_full_price(val.as_kwh_price_eur(), point.position.clone(), point.out_quantity_quantity.parse().unwrap())
+                            }
                         }
                     }
                 };
 
                 payload.data.insert(key, Data {
                     wh: point.out_quantity_quantity.parse().unwrap(),
-                    cost: price
+                    cost: price,
+                    spot_price,
+                    tariffs: meter_point_charges.map(|charges| charges.result.get_tariff_prices(&point.position).into_iter().collect()).unwrap_or_default()
                 });
             }
         }
 
-        return payload
+        payload
     }
 
-    pub fn new_daily(source : GetMeteringDataTimeSeriesResponseResult, prices : &HashMap<String, Record>, meter_point_charges : &GetMeteringPointChargesResponseResult) -> Self {
+    pub fn new_daily(source : GetMeteringDataTimeSeriesResponseResult, prices : &HashMap<String, Record>, meter_point_charges : Option<&GetMeteringPointChargesResponseResult>) -> Self {
         let mut payload = Self::new(Granularity::Daily);
 
-        let ts = source.my_energy_data_market_document.time_series.last().unwrap();
+        let ts = match source.my_energy_data_market_document.time_series.last() {
+            None => return payload,
+            Some(val) => val
+        };
 
         for period in &ts.period {
             let parsed_date = period.time_interval.end.clone().parse::<chrono::DateTime<chrono::Utc>>().unwrap();
@@ -89,28 +105,55 @@ impl UsageTimeSeries {
                         if point.out_quantity_quantity.eq("0.0") {
                             0.0
                         } else {
-                            meter_point_charges.result.get_full_price(val.as_kwh_price_eur(), point.position.clone(), point.out_quantity_quantity.parse().unwrap())
+                            match meter_point_charges {
+                                None => val.as_kwh_price_eur(),
+                                Some(charges) => charges.result.get_full_price(val.as_kwh_price_eur(), point.position.clone(), point.out_quantity_quantity.parse().unwrap())
+                            }
                         }
                     }
                 };
 
-                total = total + point.out_quantity_quantity.parse::<f64>().unwrap();
-                total_price = total_price + price;
+                total += point.out_quantity_quantity.parse::<f64>().unwrap();
+                total_price += price;
             }
             payload.data.insert(key, Data {
                 wh: f64::trunc(total * 100.0) / 100.0,
-                cost: total_price
+                cost: total_price,
+                spot_price: 0.0,
+                tariffs: BTreeMap::new()
             });
         }
 
-        return payload
+        payload
+    }
+
+    /// Adds the entries of another series, overwriting the ones already present
+    pub fn merge(&mut self, other : UsageTimeSeries) {
+        self.data.extend(other.data);
+    }
+
+    /// Returns the most recent entry, ordered by time rather than by the string key.
+    pub fn latest(&self) -> Option<(chrono::NaiveDateTime, &Data)> {
+        self.data.iter()
+            .filter_map(|(key, data)| self.granularity.parse_key(key).map(|time| (time, data)))
+            .max_by_key(|(time, _)| *time)
     }
 
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Granularity {
     Hourly,
     Daily,
     Monthly
-}
\ No newline at end of file
+}
+
+impl Granularity {
+    pub fn parse_key(&self, key : &str) -> Option<chrono::NaiveDateTime> {
+        match self {
+            Granularity::Hourly => chrono::NaiveDateTime::parse_from_str(key, "%m/%d/%Y %H:%M").ok(),
+            Granularity::Daily => chrono::NaiveDate::parse_from_str(key, "%m/%d/%Y").ok().and_then(|date| date.and_hms_opt(0, 0, 0)),
+            Granularity::Monthly => chrono::NaiveDate::parse_from_str(&format!("01/{}", key), "%d/%m/%Y").ok().and_then(|date| date.and_hms_opt(0, 0, 0)),
+        }
+    }
+}
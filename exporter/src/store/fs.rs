@@ -1,39 +1,150 @@
 use std::io::Write;
-use crate::store::{Store, StoreType};
+use std::path::{Path, PathBuf};
+use crate::store::{aggregate, merge_meter_readings, merge_timeseries, timeseries_readings, RangeQuery, Reading, Store, StoreKind, StoreType};
 use crate::error::Result;
 
+/// Stores every document as a file in `{path}/{kind}/{key}`.
+/// Documents written before they were split by kind are still read from `{path}/{key}`, and move on their next write.
 pub struct FsStore {
     pub path : String
 }
 
+impl FsStore {
+    fn kind_path(&self, kind : StoreKind) -> PathBuf {
+        let mut path_buf = PathBuf::new();
+        path_buf.push(&self.path);
+        path_buf.push(kind.as_str());
+        path_buf
+    }
+
+    // Only the kinds that existed in the flat layout
+    fn legacy_path(&self, kind : StoreKind, key : &str) -> Option<PathBuf> {
+        match kind {
+            StoreKind::String | StoreKind::MeterDataTimeSeries | StoreKind::UsageTimeSeries => Some(Path::new(&self.path).join(key)),
+            _ => None
+        }
+    }
+}
+
 impl Store for FsStore {
     fn put(&self, doc: StoreType) -> Result<()> {
-        let mut file_name : String;
-        let mut content : Vec<u8> = Vec::new();
+        let kind = doc.kind();
+        let file_name = doc.key();
+        let doc = match doc {
+            StoreType::MeterDataTimeSeries(resp) => StoreType::MeterDataTimeSeries(merge_timeseries(self.get(kind, &file_name)?, resp)),
+            StoreType::MeterReadings(resp) => StoreType::MeterReadings(merge_meter_readings(self.get(kind, &file_name)?, resp)),
+            doc => doc
+        };
+        let content = doc.to_vec()?;
 
-        match &doc {
-            StoreType::String { key, value } => {
-                file_name = key.clone();
-                content = value.as_bytes().to_vec();
-            }
-            StoreType::MeterDataTimeSeries(resp) => {
-                file_name = resp.id.clone();
-                content = serde_json::to_vec(resp).unwrap();
+        let mut path_buf = self.kind_path(kind);
+        std::fs::create_dir_all(path_buf.as_path())?;
+        path_buf.push(file_name);
+
+        let mut file = std::fs::File::create(path_buf.as_path())?;
+        file.write_all(&content)?;
+
+        Ok(())
+    }
+
+    fn get(&self, kind: StoreKind, key: &str) -> Result<Option<StoreType>> {
+        let mut path_buf = self.kind_path(kind);
+        path_buf.push(key);
+
+        let content = match std::fs::read(path_buf.as_path()) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => match self.legacy_path(kind, key).filter(|path| path.is_file()) {
+                Some(path) => std::fs::read(path)?,
+                None => return Ok(None)
             },
-            StoreType::UsageTimeSeries{key, value} => {
-                file_name = key.clone();
-                content = serde_json::to_vec(value).unwrap();
+            Err(err) => return Err(err.into())
+        };
+
+        Ok(Some(StoreType::from_slice(kind, key, &content)?))
+    }
+
+    fn list(&self, kind: StoreKind) -> Result<Vec<String>> {
+        let entries = match std::fs::read_dir(self.kind_path(kind)) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into())
+        };
+
+        let mut keys = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                keys.push(entry.file_name().to_string_lossy().into_owned());
             }
         }
+        keys.sort();
 
-        let mut path_buf = std::path::PathBuf::new();
-        path_buf.push(&self.path);
-        std::fs::create_dir_all(path_buf.as_path()).unwrap();
-        path_buf.push(file_name);
+        Ok(keys)
+    }
+
+    fn query(&self, query: &RangeQuery) -> Result<Vec<Reading>> {
+        match self.get(StoreKind::MeterDataTimeSeries, &query.metering_point_id)? {
+            Some(StoreType::MeterDataTimeSeries(resp)) => Ok(aggregate(timeseries_readings(&resp)?, query)),
+            _ => Ok(Vec::new())
+        }
+    }
+}
 
-        let mut file = std::fs::File::create(path_buf.as_path()).unwrap();
-        file.write_all(&content).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Granularity;
+    use crate::store::tests::{meter_readings_fixture, timeseries_fixture};
 
-        Ok(())
+    #[test]
+    fn put_then_read_back() {
+        let path = std::env::temp_dir().join(format!("eloverblik-store-test-{}", std::process::id()));
+        let store = FsStore { path: path.to_string_lossy().into_owned() };
+
+        store.put(StoreType::MeterDataTimeSeries(timeseries_fixture())).unwrap();
+        store.put(StoreType::String { key: "note".to_owned(), value: "hello".to_owned() }).unwrap();
+
+        assert_eq!(store.list(StoreKind::MeterDataTimeSeries).unwrap(), vec!["571313100000000000".to_owned()]);
+        assert!(matches!(store.get(StoreKind::String, "note").unwrap(), Some(StoreType::String { value, .. }) if value == "hello"));
+        assert!(store.get(StoreKind::String, "missing").unwrap().is_none());
+
+        let readings = store.query(&RangeQuery {
+            metering_point_id: "571313100000000000".to_owned(),
+            from: "2023-08-01T23:00:00Z".parse().unwrap(),
+            to: "2023-08-02T00:00:00Z".parse().unwrap(),
+            granularity: Granularity::Hourly,
+        }).unwrap();
+        assert_eq!(readings, vec![Reading { time: "2023-08-01T23:00:00Z".parse().unwrap(), quantity: 0.25 }]);
+
+        // Readings accumulate across syncs, and a corrected reading replaces the earlier one
+        store.put(StoreType::MeterReadings(meter_readings_fixture("2023-08-01T22:00:00Z", "12345.000"))).unwrap();
+        store.put(StoreType::MeterReadings(meter_readings_fixture("2023-08-02T22:00:00Z", "12350.000"))).unwrap();
+        store.put(StoreType::MeterReadings(meter_readings_fixture("2023-08-01T22:00:00Z", "12345.678"))).unwrap();
+        match store.get(StoreKind::MeterReadings, "571313100000000000").unwrap() {
+            Some(StoreType::MeterReadings(resp)) => {
+                let values : Vec<&str> = resp.result.readings.iter().map(|val| val.meter_reading.as_str()).collect();
+                assert_eq!(values, vec!["12345.678", "12350.000"]);
+            }
+            _ => panic!("meter readings were not stored")
+        }
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn reads_documents_from_the_flat_layout() {
+        let path = std::env::temp_dir().join(format!("eloverblik-store-legacy-test-{}", std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("571313100000000000"), serde_json::to_vec(&timeseries_fixture()).unwrap()).unwrap();
+        let store = FsStore { path: path.to_string_lossy().into_owned() };
+
+        assert!(matches!(store.get(StoreKind::MeterDataTimeSeries, "571313100000000000").unwrap(), Some(StoreType::MeterDataTimeSeries(_))));
+        assert!(store.get(StoreKind::MeterReadings, "571313100000000000").unwrap().is_none());
+
+        // The next write goes to the new layout, merged with what the old file held
+        store.put(StoreType::MeterDataTimeSeries(timeseries_fixture())).unwrap();
+        assert!(path.join("meter_data_timeseries").join("571313100000000000").is_file());
+
+        std::fs::remove_dir_all(path).unwrap();
     }
-}
\ No newline at end of file
+}
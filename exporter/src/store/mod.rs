@@ -1,15 +1,246 @@
 pub mod fs;
+pub mod postgres;
 
-use eloverblik_client::model::response::{GetMeteringDataTimeSeriesResponseResult};
-use crate::error::Result;
-use crate::model::UsageTimeSeries;
+use std::collections::BTreeMap;
+use chrono::{DateTime, Datelike, DurationRound, TimeZone, Utc};
+use eloverblik_client::model::response::{GetMeterReadingsResponseResult, GetMeteringDataTimeSeriesResponseResult, GetMeteringPointChargesResponseResult, GetMeteringPointsResponseResult};
+use energidataservice_client::model::response::ElSpotPricesResponse;
+use crate::error::{Error, Result};
+use crate::model::{Granularity, UsageTimeSeries};
 
 pub trait Store {
     fn put(&self, doc : StoreType) -> Result<()>;
+    fn get(&self, kind : StoreKind, key : &str) -> Result<Option<StoreType>>;
+    /// Keys of every stored document of the given kind
+    // Nothing in the exporter enumerates documents yet, it is here for every store to support
+    #[allow(dead_code)]
+    fn list(&self, kind : StoreKind) -> Result<Vec<String>>;
+    /// Stored readings of a metering point in `[from, to)`, summed up to the requested granularity
+    fn query(&self, query : &RangeQuery) -> Result<Vec<Reading>>;
 }
 
+#[allow(clippy::large_enum_variant)]
 pub enum StoreType {
     String{ key: String, value : String },
     MeterDataTimeSeries(GetMeteringDataTimeSeriesResponseResult),
-    UsageTimeSeries{ key: String, value : UsageTimeSeries}
-}
\ No newline at end of file
+    MeterReadings(GetMeterReadingsResponseResult),
+    UsageTimeSeries{ key: String, value : UsageTimeSeries},
+    MeteringPoint(GetMeteringPointsResponseResult),
+    MeteringPointCharges(GetMeteringPointChargesResponseResult),
+    SpotPrices(ElSpotPricesResponse),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StoreKind {
+    String,
+    MeterDataTimeSeries,
+    MeterReadings,
+    UsageTimeSeries,
+    MeteringPoint,
+    MeteringPointCharges,
+    SpotPrices,
+}
+
+#[derive(Clone, Debug)]
+pub struct RangeQuery {
+    pub metering_point_id : String,
+    pub from : DateTime<Utc>,
+    pub to : DateTime<Utc>,
+    pub granularity : Granularity,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reading {
+    /// Start of the hour, day or month the quantity covers, in UTC
+    pub time : DateTime<Utc>,
+    pub quantity : f64,
+}
+
+impl StoreKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StoreKind::String => "string",
+            StoreKind::MeterDataTimeSeries => "meter_data_timeseries",
+            StoreKind::MeterReadings => "meter_readings",
+            StoreKind::UsageTimeSeries => "usage_timeseries",
+            StoreKind::MeteringPoint => "metering_point",
+            StoreKind::MeteringPointCharges => "metering_point_charges",
+            StoreKind::SpotPrices => "spot_prices",
+        }
+    }
+}
+
+impl StoreType {
+    pub fn kind(&self) -> StoreKind {
+        match self {
+            StoreType::String { .. } => StoreKind::String,
+            StoreType::MeterDataTimeSeries(_) => StoreKind::MeterDataTimeSeries,
+            StoreType::MeterReadings(_) => StoreKind::MeterReadings,
+            StoreType::UsageTimeSeries { .. } => StoreKind::UsageTimeSeries,
+            StoreType::MeteringPoint(_) => StoreKind::MeteringPoint,
+            StoreType::MeteringPointCharges(_) => StoreKind::MeteringPointCharges,
+            StoreType::SpotPrices(_) => StoreKind::SpotPrices,
+        }
+    }
+
+    pub fn key(&self) -> String {
+        match self {
+            StoreType::String { key, .. } => key.clone(),
+            StoreType::MeterDataTimeSeries(resp) => resp.id.clone(),
+            StoreType::MeterReadings(resp) => resp.id.clone(),
+            StoreType::UsageTimeSeries { key, .. } => key.clone(),
+            StoreType::MeteringPoint(point) => point.metering_point_id.clone(),
+            StoreType::MeteringPointCharges(charges) => charges.id.clone(),
+            StoreType::SpotPrices(_) => "prices".to_owned(),
+        }
+    }
+
+    pub fn to_vec(&self) -> Result<Vec<u8>> {
+        let content = match self {
+            StoreType::String { value, .. } => value.as_bytes().to_vec(),
+            StoreType::MeterDataTimeSeries(resp) => serde_json::to_vec(resp)?,
+            StoreType::MeterReadings(resp) => serde_json::to_vec(resp)?,
+            StoreType::UsageTimeSeries { value, .. } => serde_json::to_vec(value)?,
+            StoreType::MeteringPoint(point) => serde_json::to_vec(point)?,
+            StoreType::MeteringPointCharges(charges) => serde_json::to_vec(charges)?,
+            StoreType::SpotPrices(prices) => serde_json::to_vec(prices)?,
+        };
+
+        Ok(content)
+    }
+
+    pub fn from_slice(kind : StoreKind, key : &str, content : &[u8]) -> Result<Self> {
+        let doc = match kind {
+            StoreKind::String => StoreType::String { key: key.to_owned(), value: String::from_utf8_lossy(content).into_owned() },
+            StoreKind::MeterDataTimeSeries => StoreType::MeterDataTimeSeries(serde_json::from_slice(content)?),
+            StoreKind::MeterReadings => StoreType::MeterReadings(serde_json::from_slice(content)?),
+            StoreKind::UsageTimeSeries => StoreType::UsageTimeSeries { key: key.to_owned(), value: serde_json::from_slice(content)? },
+            StoreKind::MeteringPoint => StoreType::MeteringPoint(serde_json::from_slice(content)?),
+            StoreKind::MeteringPointCharges => StoreType::MeteringPointCharges(serde_json::from_slice(content)?),
+            StoreKind::SpotPrices => StoreType::SpotPrices(serde_json::from_slice(content)?),
+        };
+
+        Ok(doc)
+    }
+}
+
+/// Merges a newly fetched timeseries into a previously stored one, so history accumulates across syncs.
+pub fn merge_timeseries(existing : Option<StoreType>, mut new : GetMeteringDataTimeSeriesResponseResult) -> GetMeteringDataTimeSeriesResponseResult {
+    if let Some(StoreType::MeterDataTimeSeries(mut existing)) = existing {
+        existing.my_energy_data_market_document.merge(new.my_energy_data_market_document);
+        new.my_energy_data_market_document = existing.my_energy_data_market_document;
+    }
+
+    new
+}
+
+/// Merges newly fetched meter readings into previously stored ones, like [`merge_timeseries`]
+pub fn merge_meter_readings(existing : Option<StoreType>, mut new : GetMeterReadingsResponseResult) -> GetMeterReadingsResponseResult {
+    if let Some(StoreType::MeterReadings(mut existing)) = existing {
+        existing.result.merge(new.result);
+        new.result = existing.result;
+    }
+
+    new
+}
+
+/// Every point of a timeseries document as (start, quantity)
+pub fn timeseries_readings(resp : &GetMeteringDataTimeSeriesResponseResult) -> Result<Vec<Reading>> {
+    let mut payload = Vec::new();
+
+    for ts in &resp.my_energy_data_market_document.time_series {
+        for period in &ts.period {
+            for point in &period.point {
+                payload.push(Reading {
+                    time: period.point_start(point).ok_or_else(|| Error::MissingData(format!("start of point {} in period {}", point.position, period.time_interval.start)))?,
+                    quantity: point.out_quantity_quantity.parse().map_err(|_| Error::MissingData(format!("quantity '{}'", point.out_quantity_quantity)))?,
+                });
+            }
+        }
+    }
+
+    Ok(payload)
+}
+
+/// Filters readings to the queried range and sums them up per hour, day or month (UTC).
+pub fn aggregate(readings : impl IntoIterator<Item = Reading>, query : &RangeQuery) -> Vec<Reading> {
+    let mut buckets : BTreeMap<DateTime<Utc>, f64> = BTreeMap::new();
+
+    for reading in readings {
+        if reading.time < query.from || reading.time >= query.to {
+            continue;
+        }
+
+        let bucket = match query.granularity {
+            Granularity::Hourly => reading.time.duration_trunc(chrono::Duration::hours(1)).unwrap_or(reading.time),
+            Granularity::Daily => reading.time.duration_trunc(chrono::Duration::days(1)).unwrap_or(reading.time),
+            Granularity::Monthly => Utc.with_ymd_and_hms(reading.time.year(), reading.time.month(), 1, 0, 0, 0).single().unwrap_or(reading.time),
+        };
+        *buckets.entry(bucket).or_insert(0.0) += reading.quantity;
+    }
+
+    buckets.into_iter()
+        .map(|(time, quantity)| Reading { time, quantity })
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn timeseries_fixture() -> GetMeteringDataTimeSeriesResponseResult {
+        serde_json::from_value(serde_json::json!({
+            "MyEnergyData_MarketDocument": {
+                "mRID": "", "createdDateTime": "2023-09-01T00:00:00Z", "sender_MarketParticipant.name": "",
+                "sender_MarketParticipant.mRID": { "codingScheme": null, "name": null },
+                "period.timeInterval": { "start": "2023-08-01T22:00:00Z", "end": "2023-08-02T22:00:00Z" },
+                "TimeSeries": [{
+                    "mRID": "571313100000000000", "businessType": "A04", "curveType": "A01", "measurement_Unit.name": "KWH",
+                    "MarketEvaluationPoint": { "mRID": { "codingScheme": "A10", "name": "571313100000000000" } },
+                    "Period": [{
+                        "resolution": "PT1H",
+                        "timeInterval": { "start": "2023-08-01T22:00:00Z", "end": "2023-08-02T22:00:00Z" },
+                        "Point": [
+                            { "position": "1", "out_Quantity.quantity": "0.5", "out_Quantity.quality": "A04" },
+                            { "position": "2", "out_Quantity.quantity": "0.25", "out_Quantity.quality": "A04" }
+                        ]
+                    }]
+                }]
+            },
+            "success": true, "errorCode": 10000, "errorText": "NoError", "id": "571313100000000000", "stackTrace": null
+        })).unwrap()
+    }
+
+    pub(crate) fn meter_readings_fixture(date : &str, value : &str) -> GetMeterReadingsResponseResult {
+        serde_json::from_value(serde_json::json!({
+            "result": {
+                "meteringPointId": "571313100000000000",
+                "readings": [{ "readingDate": date, "registrationDate": date, "meterNumber": "1234567", "meterReading": value, "measurementUnit": "KWH" }]
+            },
+            "success": true, "errorCode": 10000, "errorText": "NoError", "id": "571313100000000000", "stackTrace": null
+        })).unwrap()
+    }
+
+    #[test]
+    fn aggregate_sums_per_day_within_range() {
+        let at = |d, h| Utc.with_ymd_and_hms(2023, 8, d, h, 0, 0).unwrap();
+        let readings = vec![
+            Reading { time: at(1, 22), quantity: 1.0 },
+            Reading { time: at(1, 23), quantity: 2.0 },
+            Reading { time: at(2, 0), quantity: 4.0 },
+            Reading { time: at(3, 0), quantity: 8.0 },
+        ];
+
+        let aggregated = aggregate(readings, &RangeQuery {
+            metering_point_id: "571313100000000000".to_owned(),
+            from: at(1, 0),
+            to: at(3, 0),
+            granularity: Granularity::Daily,
+        });
+
+        assert_eq!(aggregated, vec![
+            Reading { time: at(1, 0), quantity: 3.0 },
+            Reading { time: at(2, 0), quantity: 4.0 },
+        ]);
+    }
+}
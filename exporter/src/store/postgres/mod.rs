@@ -0,0 +1,398 @@
+mod schema;
+
+use std::sync::Mutex;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use tracing::debug;
+use crate::error::{Error, Result};
+use crate::store::{aggregate, merge_meter_readings, RangeQuery, Reading, Store, StoreKind, StoreType};
+use self::schema::{charges, documents, meter_readings, metering_points, readings, spot_prices};
+
+const MIGRATIONS : EmbeddedMigrations = embed_migrations!("migrations");
+
+pub struct PostgresStore {
+    conn : Mutex<PgConnection>
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = metering_points)]
+struct NewMeteringPoint {
+    metering_point_id : String,
+    type_of_mp : String,
+    settlement_method : String,
+    meter_number : String,
+    postcode : String,
+    city_name : String,
+    balance_supplier_name : String,
+    data : serde_json::Value,
+    updated_at : DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = readings)]
+struct NewReading {
+    metering_point_id : String,
+    reading_time : DateTime<Utc>,
+    resolution : String,
+    quantity : f64,
+    quality : String,
+    unit : String,
+    updated_at : DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = meter_readings)]
+struct NewMeterReading {
+    metering_point_id : String,
+    meter_number : String,
+    reading_time : DateTime<Utc>,
+    registration_date : String,
+    meter_reading : f64,
+    unit : String,
+    updated_at : DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = spot_prices)]
+struct NewSpotPrice {
+    price_area : String,
+    hour_utc : DateTime<Utc>,
+    spot_price_dkk : f64,
+    spot_price_eur : f64,
+    updated_at : DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = charges)]
+struct NewCharge {
+    metering_point_id : String,
+    charge_type : String,
+    name : String,
+    owner : String,
+    valid_from_date : String,
+    position : String,
+    price : f64,
+    description : String,
+    period_type : String,
+    updated_at : DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = documents)]
+struct NewDocument {
+    kind : String,
+    key : String,
+    value : String,
+    updated_at : DateTime<Utc>,
+}
+
+impl PostgresStore {
+    /// Connects to the database and applies any pending migrations.
+    pub fn connect(url : &str) -> Result<Self> {
+        let mut conn = PgConnection::establish(url)?;
+        let applied = conn.run_pending_migrations(MIGRATIONS).map_err(|err| Error::DatabaseError(err))?;
+        debug!("Applied {} migrations", applied.len());
+
+        Ok(Self {
+            conn: Mutex::new(conn)
+        })
+    }
+
+    /// Keeps the document as sent, so `get` and `list` work for every kind
+    fn put_document(conn : &mut PgConnection, doc : &StoreType) -> Result<()> {
+        let value = String::from_utf8_lossy(&doc.to_vec()?).into_owned();
+        diesel::insert_into(documents::table)
+            .values(NewDocument { kind: doc.kind().as_str().to_owned(), key: doc.key(), value, updated_at: Utc::now() })
+            .on_conflict((documents::kind, documents::key))
+            .do_update()
+            .set((documents::value.eq(excluded(documents::value)), documents::updated_at.eq(excluded(documents::updated_at))))
+            .execute(conn)?;
+
+        Ok(())
+    }
+}
+
+impl Store for PostgresStore {
+    fn put(&self, doc: StoreType) -> Result<()> {
+        // Timeseries history lives in the readings table, so only the latest fetched document is kept of those.
+        // Meter readings are few enough to accumulate in the kept document.
+        let merged = match &doc {
+            StoreType::MeterReadings(resp) => Some(StoreType::MeterReadings(merge_meter_readings(self.get(StoreKind::MeterReadings, &resp.id)?, resp.clone()))),
+            _ => None
+        };
+
+        let mut conn = self.conn.lock().map_err(|_| Error::LockPoisoned)?;
+        let conn = &mut *conn;
+        let now = Utc::now();
+
+        Self::put_document(conn, merged.as_ref().unwrap_or(&doc))?;
+
+        match doc {
+            StoreType::String { .. } | StoreType::UsageTimeSeries { .. } => {}
+            StoreType::MeterDataTimeSeries(resp) => {
+                let mut rows = Vec::new();
+                for ts in &resp.my_energy_data_market_document.time_series {
+                    for period in &ts.period {
+                        for point in &period.point {
+                            let reading_time = period.point_start(point).ok_or_else(|| Error::MissingData(format!("start of point {} in period {}", point.position, period.time_interval.start)))?;
+                            rows.push(NewReading {
+                                metering_point_id: ts.market_evaluation_point.m_rid.name.clone(),
+                                reading_time,
+                                resolution: period.resolution.clone(),
+                                quantity: point.out_quantity_quantity.parse().map_err(|_| Error::MissingData(format!("quantity '{}'", point.out_quantity_quantity)))?,
+                                quality: point.out_quantity_quality.clone(),
+                                unit: ts.measurement_unit_name.clone(),
+                                updated_at: now,
+                            });
+                        }
+                    }
+                }
+
+                conn.transaction(|conn| {
+                    // Stay well below the 65535 bind parameter limit of a single statement
+                    for chunk in rows.chunks(1000) {
+                        diesel::insert_into(readings::table)
+                            .values(chunk)
+                            .on_conflict((readings::metering_point_id, readings::reading_time))
+                            .do_update()
+                            .set((
+                                readings::resolution.eq(excluded(readings::resolution)),
+                                readings::quantity.eq(excluded(readings::quantity)),
+                                readings::quality.eq(excluded(readings::quality)),
+                                readings::unit.eq(excluded(readings::unit)),
+                                readings::updated_at.eq(excluded(readings::updated_at)),
+                            ))
+                            .execute(conn)?;
+                    }
+                    diesel::QueryResult::Ok(())
+                })?;
+            }
+            StoreType::MeterReadings(resp) => {
+                let mut rows = Vec::new();
+                for reading in &resp.result.readings {
+                    rows.push(NewMeterReading {
+                        metering_point_id: resp.id.clone(),
+                        meter_number: reading.meter_number.clone(),
+                        reading_time: reading.reading_time().ok_or_else(|| Error::MissingData(format!("reading date '{}'", reading.reading_date)))?,
+                        registration_date: reading.registration_date.clone(),
+                        meter_reading: reading.value().ok_or_else(|| Error::MissingData(format!("meter reading '{}'", reading.meter_reading)))?,
+                        unit: reading.measurement_unit.clone(),
+                        updated_at: now,
+                    });
+                }
+
+                if rows.is_empty() {
+                    return Ok(());
+                }
+
+                diesel::insert_into(meter_readings::table)
+                    .values(&rows)
+                    .on_conflict((meter_readings::metering_point_id, meter_readings::meter_number, meter_readings::reading_time))
+                    .do_update()
+                    .set((
+                        meter_readings::registration_date.eq(excluded(meter_readings::registration_date)),
+                        meter_readings::meter_reading.eq(excluded(meter_readings::meter_reading)),
+                        meter_readings::unit.eq(excluded(meter_readings::unit)),
+                        meter_readings::updated_at.eq(excluded(meter_readings::updated_at)),
+                    ))
+                    .execute(conn)?;
+            }
+            StoreType::MeteringPoint(point) => {
+                diesel::insert_into(metering_points::table)
+                    .values(NewMeteringPoint {
+                        metering_point_id: point.metering_point_id.clone(),
+                        type_of_mp: point.type_of_mp.clone(),
+                        settlement_method: point.settlement_method.clone(),
+                        meter_number: point.meter_number.clone(),
+                        postcode: point.postcode.clone(),
+                        city_name: point.city_name.clone(),
+                        balance_supplier_name: point.balance_supplier_name.clone(),
+                        data: serde_json::to_value(&point)?,
+                        updated_at: now,
+                    })
+                    .on_conflict(metering_points::metering_point_id)
+                    .do_update()
+                    .set((
+                        metering_points::type_of_mp.eq(excluded(metering_points::type_of_mp)),
+                        metering_points::settlement_method.eq(excluded(metering_points::settlement_method)),
+                        metering_points::meter_number.eq(excluded(metering_points::meter_number)),
+                        metering_points::postcode.eq(excluded(metering_points::postcode)),
+                        metering_points::city_name.eq(excluded(metering_points::city_name)),
+                        metering_points::balance_supplier_name.eq(excluded(metering_points::balance_supplier_name)),
+                        metering_points::data.eq(excluded(metering_points::data)),
+                        metering_points::updated_at.eq(excluded(metering_points::updated_at)),
+                    ))
+                    .execute(conn)?;
+            }
+            StoreType::MeteringPointCharges(resp) => {
+                let mut rows = Vec::new();
+                let metering_point_id = resp.result.metering_point_id.clone();
+                for tariff in resp.result.tariffs {
+                    for price in tariff.prices {
+                        rows.push(NewCharge {
+                            metering_point_id: metering_point_id.clone(),
+                            charge_type: "tariff".to_owned(),
+                            name: tariff.name.clone(),
+                            owner: tariff.owner.clone(),
+                            valid_from_date: tariff.valid_from_date.clone(),
+                            position: price.position,
+                            price: price.price,
+                            description: tariff.description.clone(),
+                            period_type: tariff.period_type.clone(),
+                            updated_at: now,
+                        });
+                    }
+                }
+                for subscription in resp.result.subscriptions {
+                    rows.push(NewCharge {
+                        metering_point_id: metering_point_id.clone(),
+                        charge_type: "subscription".to_owned(),
+                        name: subscription.name,
+                        owner: subscription.owner,
+                        valid_from_date: subscription.valid_from_date,
+                        position: "".to_owned(),
+                        price: subscription.price,
+                        description: subscription.description,
+                        period_type: subscription.period_type,
+                        updated_at: now,
+                    });
+                }
+
+                if rows.is_empty() {
+                    return Ok(());
+                }
+
+                diesel::insert_into(charges::table)
+                    .values(&rows)
+                    .on_conflict((charges::metering_point_id, charges::charge_type, charges::name, charges::owner, charges::valid_from_date, charges::position))
+                    .do_update()
+                    .set((
+                        charges::price.eq(excluded(charges::price)),
+                        charges::description.eq(excluded(charges::description)),
+                        charges::period_type.eq(excluded(charges::period_type)),
+                        charges::updated_at.eq(excluded(charges::updated_at)),
+                    ))
+                    .execute(conn)?;
+            }
+            StoreType::SpotPrices(resp) => {
+                let mut rows = Vec::new();
+                for record in &resp.records {
+                    rows.push(NewSpotPrice {
+                        price_area: record.price_area.clone(),
+                        hour_utc: record.hour_utc_to_datetime().map_err(|_| Error::MissingData(format!("hour '{}'", record.hour_utc)))?,
+                        spot_price_dkk: record.spot_price_dkk,
+                        spot_price_eur: record.spot_price_eur,
+                        updated_at: now,
+                    });
+                }
+
+                conn.transaction(|conn| {
+                    for chunk in rows.chunks(1000) {
+                        diesel::insert_into(spot_prices::table)
+                            .values(chunk)
+                            .on_conflict((spot_prices::price_area, spot_prices::hour_utc))
+                            .do_update()
+                            .set((
+                                spot_prices::spot_price_dkk.eq(excluded(spot_prices::spot_price_dkk)),
+                                spot_prices::spot_price_eur.eq(excluded(spot_prices::spot_price_eur)),
+                                spot_prices::updated_at.eq(excluded(spot_prices::updated_at)),
+                            ))
+                            .execute(conn)?;
+                    }
+                    diesel::QueryResult::Ok(())
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get(&self, kind: StoreKind, key: &str) -> Result<Option<StoreType>> {
+        let mut conn = self.conn.lock().map_err(|_| Error::LockPoisoned)?;
+        let value : Option<String> = documents::table
+            .filter(documents::kind.eq(kind.as_str()))
+            .filter(documents::key.eq(key))
+            .select(documents::value)
+            .first(&mut *conn)
+            .optional()?;
+
+        match value {
+            None => Ok(None),
+            Some(value) => Ok(Some(StoreType::from_slice(kind, key, value.as_bytes())?))
+        }
+    }
+
+    fn list(&self, kind: StoreKind) -> Result<Vec<String>> {
+        let mut conn = self.conn.lock().map_err(|_| Error::LockPoisoned)?;
+        let keys = documents::table
+            .filter(documents::kind.eq(kind.as_str()))
+            .select(documents::key)
+            .order(documents::key)
+            .load(&mut *conn)?;
+
+        Ok(keys)
+    }
+
+    fn query(&self, query: &RangeQuery) -> Result<Vec<Reading>> {
+        let mut conn = self.conn.lock().map_err(|_| Error::LockPoisoned)?;
+        let rows : Vec<(DateTime<Utc>, f64)> = readings::table
+            .filter(readings::metering_point_id.eq(&query.metering_point_id))
+            .filter(readings::reading_time.ge(query.from))
+            .filter(readings::reading_time.lt(query.to))
+            .select((readings::reading_time, readings::quantity))
+            .order(readings::reading_time)
+            .load(&mut *conn)?;
+
+        Ok(aggregate(rows.into_iter().map(|(time, quantity)| Reading { time, quantity }), query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Needs a scratch database, e.g. ELOVERBLIK_EXPORTER_TEST_POSTGRES_URL=postgres://postgres@localhost/postgres cargo test -- --ignored
+    #[test]
+    #[ignore]
+    fn put_readings_is_idempotent() {
+        let url = std::env::var("ELOVERBLIK_EXPORTER_TEST_POSTGRES_URL").unwrap();
+        let store = PostgresStore::connect(&url).unwrap();
+        let doc = crate::store::tests::timeseries_fixture();
+
+        store.put(StoreType::MeterDataTimeSeries(doc.clone())).unwrap();
+        store.put(StoreType::MeterDataTimeSeries(doc)).unwrap();
+
+        {
+            let mut conn = store.conn.lock().unwrap();
+            let count : i64 = readings::table
+                .filter(readings::metering_point_id.eq("571313100000000000"))
+                .count()
+                .get_result(&mut *conn)
+                .unwrap();
+            assert_eq!(count, 2);
+        }
+
+        assert!(store.list(StoreKind::MeterDataTimeSeries).unwrap().contains(&"571313100000000000".to_owned()));
+        assert!(matches!(store.get(StoreKind::MeterDataTimeSeries, "571313100000000000").unwrap(), Some(StoreType::MeterDataTimeSeries(_))));
+        let readings = store.query(&RangeQuery {
+            metering_point_id: "571313100000000000".to_owned(),
+            from: "2023-08-01T00:00:00Z".parse().unwrap(),
+            to: "2023-08-03T00:00:00Z".parse().unwrap(),
+            granularity: crate::model::Granularity::Daily,
+        }).unwrap();
+        assert_eq!(readings, vec![Reading { time: "2023-08-01T00:00:00Z".parse().unwrap(), quantity: 0.75 }]);
+
+        let meter_reading = crate::store::tests::meter_readings_fixture("2023-08-01T22:00:00Z", "12345.678");
+        store.put(StoreType::MeterReadings(meter_reading.clone())).unwrap();
+        store.put(StoreType::MeterReadings(meter_reading)).unwrap();
+        let mut conn = store.conn.lock().unwrap();
+        let value : f64 = meter_readings::table
+            .filter(meter_readings::metering_point_id.eq("571313100000000000"))
+            .select(meter_readings::meter_reading)
+            .first(&mut *conn)
+            .unwrap();
+        assert_eq!(value, 12345.678);
+    }
+}
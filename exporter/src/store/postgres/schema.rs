@@ -0,0 +1,71 @@
+diesel::table! {
+    metering_points (metering_point_id) {
+        metering_point_id -> Text,
+        type_of_mp -> Text,
+        settlement_method -> Text,
+        meter_number -> Text,
+        postcode -> Text,
+        city_name -> Text,
+        balance_supplier_name -> Text,
+        data -> Jsonb,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    readings (metering_point_id, reading_time) {
+        metering_point_id -> Text,
+        reading_time -> Timestamptz,
+        resolution -> Text,
+        quantity -> Float8,
+        quality -> Text,
+        unit -> Text,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    meter_readings (metering_point_id, meter_number, reading_time) {
+        metering_point_id -> Text,
+        meter_number -> Text,
+        reading_time -> Timestamptz,
+        registration_date -> Text,
+        meter_reading -> Float8,
+        unit -> Text,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    spot_prices (price_area, hour_utc) {
+        price_area -> Text,
+        hour_utc -> Timestamptz,
+        spot_price_dkk -> Float8,
+        spot_price_eur -> Float8,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    charges (metering_point_id, charge_type, name, owner, valid_from_date, position) {
+        metering_point_id -> Text,
+        charge_type -> Text,
+        name -> Text,
+        owner -> Text,
+        valid_from_date -> Text,
+        position -> Text,
+        price -> Float8,
+        description -> Text,
+        period_type -> Text,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    documents (kind, key) {
+        key -> Text,
+        value -> Text,
+        updated_at -> Timestamptz,
+        kind -> Text,
+    }
+}
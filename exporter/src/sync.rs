@@ -0,0 +1,574 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use chrono::{DateTime, NaiveDate, Utc};
+use eloverblik_client::error::{ApiError, ApiErrorCode};
+use eloverblik_client::model::request::{Aggregation, GetMeterReadingsRequest, GetMeteringDataTimeSeriesRequest, GetMeteringPointChargesRequest, GetMeteringPointDetailsRequest, MeteringPoints};
+use eloverblik_client::model::response::{GetMeterReadingsResponseResult, GetMeteringDataTimeSeriesResponseResult, GetMeteringPointChargesResponseResult, GetMeteringPointsResponse, MyEnergyDataMarketDocument};
+use energidataservice_client::model::request::ElSpotPricesRequest;
+use energidataservice_client::model::response::{ElSpotPricesResponse, Record};
+use tracing::{debug, info, warn};
+use crate::config::{Config, SecretFile};
+use crate::error::{Error, Result};
+use crate::metrics::{MeteringPointLabels, Metrics};
+use crate::model::{Granularity, UsageTimeSeries};
+use crate::store::{timeseries_readings, RangeQuery, Store, StoreKind, StoreType};
+
+// Number of metering points sent in a single timeseries or charges request
+const BATCH_SIZE : usize = 10;
+
+pub struct Syncer {
+    pub conf : Config,
+    pub client : eloverblik_client::Client,
+    pub eds_client : energidataservice_client::Client,
+    pub stores : Vec<Box<dyn Store>>,
+    pub metrics : Metrics,
+    /// Where a rotated refresh token is picked up from, see `eloverblik_refresh_token_file`
+    pub refresh_token_file : Option<SecretFile>,
+}
+
+impl Syncer {
+    pub async fn run(&self) -> Result<()> {
+        // Eloverblik lags about a day behind and the end date is exclusive, so this fetches up to and including yesterday
+        let end = Utc::now().date_naive();
+        self.check_refresh_token()?;
+
+        let metering_points = self.client.get_metering_points().await?;
+        let points = collect_points(&metering_points)?;
+        self.update_details(&points).await;
+
+        // Points are grouped by where they left off, so they can still be fetched in batches
+        let mut pending : BTreeMap<NaiveDate, Vec<String>> = BTreeMap::new();
+        for (id, _) in &points {
+            let start = self.sync_start(id, end)?;
+            if start >= end {
+                debug!("Metering point {} is up to date", id);
+                continue;
+            }
+            pending.entry(start).or_default().push(id.clone());
+        }
+
+        self.sync(&metering_points, &points, pending, end).await
+    }
+
+    /// Fetches and stores `[start, end)` for every metering point on the account, regardless of what has been synced before
+    pub async fn backfill(&self, start : NaiveDate, end : NaiveDate) -> Result<()> {
+        self.check_refresh_token()?;
+        let metering_points = self.client.get_metering_points().await?;
+        let points = collect_points(&metering_points)?;
+        self.update_details(&points).await;
+        let pending = BTreeMap::from([(start, points.iter().map(|(id, _)| id.clone()).collect())]);
+
+        self.sync(&metering_points, &points, pending, end).await
+    }
+
+    /// Stores timeseries from a CSV export, e.g. downloaded from the Eloverblik portal, priced like synced ones.
+    /// Only hourly exports can be imported, as usage is kept per hour. A later sync continues from where the export ends.
+    pub async fn import(&self, mut document : MyEnergyDataMarketDocument) -> Result<()> {
+        if let Some(period) = document.time_series.iter().flat_map(|ts| &ts.period).find(|period| period.resolution != "PT1H") {
+            return Err(Error::InvalidImport(format!("{} readings from {}, export hourly readings instead", period.resolution, period.time_interval.start)));
+        }
+        let parse = |val : &str| val.parse::<DateTime<Utc>>().map_err(|_| Error::MissingData(format!("export period '{}'", val)));
+        let start = parse(&document.period_time_interval.start)?;
+        let end = parse(&document.period_time_interval.end)?;
+
+        self.check_refresh_token()?;
+        let metering_points = self.client.get_metering_points().await?;
+        let points = collect_points(&metering_points)?;
+
+        let time_series = std::mem::take(&mut document.time_series);
+        let ids : Vec<String> = time_series.iter().map(|ts| ts.market_evaluation_point.m_rid.name.clone()).collect();
+        let charges = self.get_charges(&ids).await?;
+        // Prices are per UTC day, so the day the export ends in is fetched in full
+        let prices = self.get_prices(start.date_naive(), end.date_naive() + chrono::Duration::days(1)).await?;
+        let prices_map = prices.clone().into_records_as_map();
+        for store in &self.stores {
+            store.put(StoreType::SpotPrices(prices.clone()))?;
+        }
+
+        for ts in time_series {
+            let id = ts.market_evaluation_point.m_rid.name.clone();
+            let type_of_mp = match points.iter().find(|(val, _)| *val == id) {
+                Some((_, val)) => val.clone(),
+                None => {
+                    warn!("Metering point {} is not on the account, importing it without a type", id);
+                    String::new()
+                }
+            };
+
+            let point_timeseries = GetMeteringDataTimeSeriesResponseResult {
+                my_energy_data_market_document: MyEnergyDataMarketDocument {
+                    time_series: vec![ts],
+                    ..document.clone()
+                },
+                success: true,
+                error_code: ApiErrorCode::NoError.code(),
+                error_text: String::new(),
+                id: id.clone(),
+                stack_trace: serde_json::Value::Null,
+            };
+            self.store_timeseries(&id, &type_of_mp, &point_timeseries, &prices_map, charges.get(&id), None)?;
+        }
+        info!("Imported {} metering points from {} to {}", ids.len(), start, end);
+
+        Ok(())
+    }
+
+    async fn sync(&self, metering_points : &GetMeteringPointsResponse, points : &[(String, String)], pending : BTreeMap<NaiveDate, Vec<String>>, end : NaiveDate) -> Result<()> {
+        let start = match pending.keys().next() {
+            None => {
+                info!("All metering points are up to date");
+                self.metrics.inc_syncs();
+                return Ok(());
+            }
+            Some(val) => *val
+        };
+        info!("Syncing {} metering points from {} to {}", pending.values().map(|ids| ids.len()).sum::<usize>(), start, end);
+
+        let mut timeseries = HashMap::new();
+        // Start of the earliest chunk that failed per metering point, which its high-water mark must not pass
+        let mut gaps : HashMap<String, DateTime<Utc>> = HashMap::new();
+        for (start, ids) in &pending {
+            for batch in ids.chunks(BATCH_SIZE) {
+                let (resp, failed_chunks) = self.client.get_metering_data_timeseries_chunked(GetMeteringDataTimeSeriesRequest {
+                    metering_points: MeteringPoints {
+                        metering_point: batch.to_vec()
+                    }
+                }, *start, end, Aggregation::Hour, self.conf.backfill_chunk_days).await?;
+                for chunk in failed_chunks {
+                    warn!("{}", chunk);
+                    self.metrics.inc_api_errors(&chunk.error.id, chunk.error.code.code());
+                    let gap = chunk.start.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+                    let earliest = gaps.entry(chunk.error.id).or_insert(gap);
+                    *earliest = (*earliest).min(gap);
+                }
+                let (answered, failed) = resp.split();
+                self.report_api_errors(&failed);
+                timeseries.extend(answered.into_iter().map(|val| (val.id.clone(), val)));
+            }
+        }
+
+        let ids : Vec<String> = pending.values().flatten().cloned().collect();
+        let charges = self.get_charges(&ids).await?;
+
+        let meter_readings = self.get_meter_readings(&pending, end).await;
+
+        let prices = self.get_prices(start, end).await?;
+        let prices_map = prices.clone().into_records_as_map();
+
+        for store in &self.stores {
+            for point in &metering_points.result {
+                store.put(StoreType::MeteringPoint(point.clone()))?;
+            }
+            store.put(StoreType::SpotPrices(prices.clone()))?;
+        }
+
+        for (id, type_of_mp) in points {
+            if let Some(val) = meter_readings.get(id) {
+                for store in &self.stores {
+                    if let Err(err) = store.put(StoreType::MeterReadings(val.clone())) {
+                        warn!("Could not store meter readings of metering point {}: {}", id, err);
+                        self.metrics.inc_skipped_fetches("meter_readings");
+                    }
+                }
+            }
+
+            match timeseries.get(id) {
+                None => continue,
+                Some(point_timeseries) => self.store_timeseries(id, type_of_mp, point_timeseries, &prices_map, charges.get(id), gaps.get(id).copied())?
+            }
+        }
+        if let Some(stats) = self.client.cache_stats() {
+            self.metrics.record_cache_stats(&stats);
+        }
+        self.metrics.inc_syncs();
+
+        Ok(())
+    }
+
+    async fn get_charges(&self, ids : &[String]) -> Result<HashMap<String, GetMeteringPointChargesResponseResult>> {
+        let mut charges = HashMap::new();
+        for batch in ids.chunks(BATCH_SIZE) {
+            let resp = self.client.get_metering_point_charges(GetMeteringPointChargesRequest {
+                metering_points: MeteringPoints {
+                    metering_point: batch.to_vec()
+                }
+            }).await?;
+            let (answered, failed) = resp.split();
+            self.report_api_errors(&failed);
+            charges.extend(answered.into_iter().map(|val| (val.id.clone(), val)));
+        }
+
+        Ok(charges)
+    }
+
+    // Readings are only used for reconciliation, so a failure is logged and counted rather than failing the sync
+    async fn get_meter_readings(&self, pending : &BTreeMap<NaiveDate, Vec<String>>, end : NaiveDate) -> HashMap<String, GetMeterReadingsResponseResult> {
+        let mut meter_readings : HashMap<String, GetMeterReadingsResponseResult> = HashMap::new();
+        for (start, ids) in pending {
+            for batch in ids.chunks(BATCH_SIZE) {
+                for (chunk_start, chunk_end) in eloverblik_client::split_range(*start, end, self.conf.backfill_chunk_days) {
+                    let resp = match self.client.get_meter_readings(GetMeterReadingsRequest {
+                        metering_points: MeteringPoints {
+                            metering_point: batch.to_vec()
+                        }
+                    }, chunk_start, chunk_end).await {
+                        Ok(val) => val,
+                        Err(err) => {
+                            warn!("Skipping meter readings from {} to {}: {}", chunk_start, chunk_end, err);
+                            self.metrics.inc_skipped_fetches("meter_readings");
+                            continue;
+                        }
+                    };
+                    let (answered, failed) = resp.split();
+                    self.report_api_errors(&failed);
+                    for result in answered {
+                        match meter_readings.get_mut(&result.id) {
+                            None => { meter_readings.insert(result.id.clone(), result); }
+                            Some(existing) => existing.result.merge(result.result)
+                        }
+                    }
+                }
+            }
+        }
+
+        meter_readings
+    }
+
+    async fn get_prices(&self, start : NaiveDate, end : NaiveDate) -> Result<ElSpotPricesResponse> {
+        Ok(self.eds_client.get_elspotprices(ElSpotPricesRequest {
+            limit: Some(0),
+            timezone: Some("UTC".to_owned()),
+            start: Some(start.format("%Y-%m-%d").to_string()),
+            end: Some(end.format("%Y-%m-%d").to_string()),
+            filter: Some(format!("{{\"PriceArea\":[\"{}\"]}}", self.conf.price_area)),
+            sort: Some("HourUTC".to_owned()),
+        }).await?)
+    }
+
+    /// Stores a fetched or imported timeseries with its priced usage, and moves the high-water mark and metrics along.
+    /// The mark stops at `gap`, so a range that failed to fetch is fetched again next time.
+    fn store_timeseries(&self, id : &str, type_of_mp : &str, point_timeseries : &GetMeteringDataTimeSeriesResponseResult, prices_map : &HashMap<String, Record>, point_charges : Option<&GetMeteringPointChargesResponseResult>, gap : Option<DateTime<Utc>>) -> Result<()> {
+        // Only the fetched window is priced, so the new entries are added to what is already stored
+        let mut hourly = self.get_usage_timeseries(&format!("{}_hourly", id), Granularity::Hourly)?;
+        hourly.merge(UsageTimeSeries::new_hourly(point_timeseries.clone(), prices_map, point_charges));
+        let mut daily = self.get_usage_timeseries(&format!("{}_daily", id), Granularity::Daily)?;
+        daily.merge(UsageTimeSeries::new_daily(point_timeseries.clone(), prices_map, point_charges));
+
+        for store in &self.stores {
+            store.put(StoreType::MeterDataTimeSeries(point_timeseries.clone()))?;
+            store.put(StoreType::UsageTimeSeries {key: format!("{}_hourly", id), value: hourly.clone()})?;
+            store.put(StoreType::UsageTimeSeries {key: format!("{}_daily", id), value: daily.clone()})?;
+            if let Some(val) = point_charges {
+                store.put(StoreType::MeteringPointCharges(val.clone()))?;
+            }
+        }
+
+        match timeseries_readings(point_timeseries)?.iter().map(|reading| reading.time).max() {
+            None => warn!("No readings returned for metering point {}", id),
+            Some(mark) => self.set_high_water_mark(id, gap.map(|gap| gap.min(mark)).unwrap_or(mark))?
+        }
+
+        self.metrics.update(&MeteringPointLabels {
+            metering_point_id: id.to_owned(),
+            price_area: self.conf.price_area.clone(),
+            type_of_mp: type_of_mp.to_owned(),
+        }, &hourly, &daily);
+
+        Ok(())
+    }
+
+    // Details rarely change, but are cheap to fetch, so they are refreshed for every point on each run.
+    // They only feed the info metric, so a failure keeps the previous series instead of failing the sync.
+    async fn update_details(&self, points : &[(String, String)]) {
+        let mut details = Vec::new();
+        for batch in points.chunks(BATCH_SIZE) {
+            let resp = self.client.get_metering_point_details(GetMeteringPointDetailsRequest {
+                metering_points: MeteringPoints {
+                    metering_point: batch.iter().map(|(id, _)| id.clone()).collect()
+                }
+            }).await;
+            let resp = match resp {
+                Ok(val) => val,
+                Err(err) => {
+                    warn!("Skipping metering point details: {}", err);
+                    self.metrics.inc_skipped_fetches("details");
+                    return;
+                }
+            };
+            let (answered, failed) = resp.split();
+            self.report_api_errors(&failed);
+
+            for result in answered {
+                let type_of_mp = batch.iter().find(|(id, _)| *id == result.id).map(|(_, val)| val.clone()).unwrap_or_default();
+                details.push((MeteringPointLabels {
+                    metering_point_id: result.id,
+                    price_area: self.conf.price_area.clone(),
+                    type_of_mp,
+                }, result.result));
+            }
+        }
+        self.metrics.set_details(&details);
+    }
+
+    // Picks up a rotated refresh token and keeps the expiry metric current. The client itself refuses to use an expired one.
+    fn check_refresh_token(&self) -> Result<()> {
+        if let Some(file) = &self.refresh_token_file {
+            if let Some(val) = file.read_if_changed() {
+                info!("Reloaded refresh token from {}", file.path);
+                self.client.set_refresh_token(val);
+            }
+        }
+
+        match self.client.refresh_token_expiry() {
+            None => debug!("Refresh token has no readable expiry"),
+            Some(expiry) => self.metrics.set_refresh_token_expiry(expiry)
+        }
+
+        Ok(())
+    }
+
+    /// First day to fetch for a metering point, either the day of its high-water mark or the start of the initial backfill window
+    fn sync_start(&self, id : &str, end : NaiveDate) -> Result<NaiveDate> {
+        let initial = end - chrono::Duration::days(self.conf.initial_backfill_days);
+
+        // The day holding the mark is fetched again, as it may have been incomplete at the time
+        Ok(match self.high_water_mark(id, initial)? {
+            None => initial,
+            Some(mark) => mark.date_naive().max(initial)
+        })
+    }
+
+    /// Start of the last synced hour of a metering point. Falls back to the readings already in the store when no mark has been kept yet.
+    fn high_water_mark(&self, id : &str, initial : NaiveDate) -> Result<Option<DateTime<Utc>>> {
+        let store = match self.stores.first() {
+            None => return Ok(None),
+            Some(val) => val
+        };
+
+        if let Some(StoreType::String { value, .. }) = store.get(StoreKind::String, &high_water_mark_key(id))? {
+            let mark = DateTime::parse_from_rfc3339(&value).map_err(|_| Error::MissingData(format!("high-water mark '{}'", value)))?;
+            return Ok(Some(mark.with_timezone(&Utc)));
+        }
+
+        let readings = store.query(&RangeQuery {
+            metering_point_id: id.to_owned(),
+            from: initial.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc(),
+            to: Utc::now(),
+            granularity: Granularity::Hourly,
+        })?;
+
+        Ok(readings.last().map(|reading| reading.time))
+    }
+
+    fn set_high_water_mark(&self, id : &str, mark : DateTime<Utc>) -> Result<()> {
+        // A backfill of older data must not move the mark back
+        if let Some(StoreType::String { value, .. }) = self.stores.first().map(|store| store.get(StoreKind::String, &high_water_mark_key(id))).transpose()?.flatten() {
+            if DateTime::parse_from_rfc3339(&value).map(|existing| existing >= mark).unwrap_or(false) {
+                return Ok(());
+            }
+        }
+
+        for store in &self.stores {
+            store.put(StoreType::String { key: high_water_mark_key(id), value: mark.to_rfc3339() })?;
+        }
+
+        Ok(())
+    }
+
+    // A metering point Eloverblik failed for is skipped, the rest of its batch is still synced
+    fn report_api_errors(&self, errors : &[ApiError]) {
+        for err in errors {
+            warn!("{}", err);
+            self.metrics.inc_api_errors(&err.id, err.code.code());
+        }
+    }
+
+    fn get_usage_timeseries(&self, key : &str, granularity : Granularity) -> Result<UsageTimeSeries> {
+        if let Some(store) = self.stores.first() {
+            if let Some(StoreType::UsageTimeSeries { value, .. }) = store.get(StoreKind::UsageTimeSeries, key)? {
+                return Ok(value);
+            }
+        }
+
+        Ok(UsageTimeSeries::new(granularity))
+    }
+}
+
+/// Ids and types of every metering point on the account, child points included
+fn collect_points(metering_points : &GetMeteringPointsResponse) -> Result<Vec<(String, String)>> {
+    let mut points : Vec<(String, String)> = Vec::new();
+    for point in &metering_points.result {
+        points.push((point.metering_point_id.clone(), point.type_of_mp.clone()));
+        points.extend(point.child_metering_points());
+    }
+    let mut seen = HashSet::new();
+    points.retain(|(id, _)| seen.insert(id.clone()));
+    if points.is_empty() {
+        return Err(Error::MissingData("metering points".to_owned()));
+    }
+
+    Ok(points)
+}
+
+fn high_water_mark_key(id : &str) -> String {
+    format!("high_water_mark_{}", id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eloverblik_client::retry::RetryPolicy;
+    use eloverblik_client::test_support::{MockServer, METERING_POINT_ID, TIMESERIES_EXPORT_FIXTURE};
+    use crate::store::fs::FsStore;
+
+    // Both APIs on one server, as their paths do not overlap
+    async fn start_mock_server() -> MockServer {
+        let server = eloverblik_client::test_support::start_mock_server().await;
+        energidataservice_client::test_support::mount(&server).await;
+        server
+    }
+
+    fn test_syncer(server : &MockServer, path : &std::path::Path) -> Syncer {
+        Syncer {
+            conf: Config {
+                price_area: "DK2".to_owned(),
+                initial_backfill_days: 31,
+                backfill_chunk_days: 90,
+                ..Default::default()
+            },
+            client: eloverblik_client::new_builder()
+                .add_base_url(&server.uri())
+                .add_retry_policy(RetryPolicy::none())
+                .build(),
+            eds_client: energidataservice_client::new_builder()
+                .add_base_url(&server.uri())
+                .add_retry_policy(energidataservice_client::retry::RetryPolicy::none())
+                .build(),
+            stores: vec![Box::new(FsStore { path: path.to_string_lossy().into_owned() })],
+            metrics: Metrics::new().unwrap(),
+            refresh_token_file: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn run_syncs_fixtures_into_store_and_metrics() {
+        let server = start_mock_server().await;
+        let path = std::env::temp_dir().join(format!("eloverblik-sync-test-{}", std::process::id()));
+        let syncer = test_syncer(&server, &path);
+
+        syncer.run().await.unwrap();
+
+        let hourly = syncer.get_usage_timeseries(&format!("{}_hourly", METERING_POINT_ID), Granularity::Hourly).unwrap();
+        let first = &hourly.data["08/02/2023 00:00"];
+        assert_eq!(first.wh, 0.5);
+        assert_eq!(first.spot_price, 0.1);
+        assert_eq!(first.tariffs["Nettarif C time"], 0.1);
+        assert!((first.cost - (0.1 + (0.058 + 0.1) * 0.5)).abs() < 1e-9);
+        assert_eq!(hourly.data.len(), 2);
+
+        let mark = syncer.high_water_mark(METERING_POINT_ID, Utc::now().date_naive()).unwrap();
+        assert_eq!(mark, Some("2023-08-01T23:00:00Z".parse().unwrap()));
+        assert!(matches!(syncer.stores[0].get(StoreKind::MeterReadings, METERING_POINT_ID).unwrap(), Some(StoreType::MeterReadings(resp)) if resp.result.readings.len() == 2));
+        let encoded = syncer.metrics.encode().unwrap();
+        assert!(encoded.contains(&format!("eloverblik_consumption_kwh{{metering_point_id=\"{}\",price_area=\"DK2\",type_of_mp=\"E17\"}} 0.25", METERING_POINT_ID)));
+        assert!(encoded.lines().any(|line| line.starts_with("eloverblik_metering_point_info{") && line.contains("grid_operator=\"Test Net A/S\"") && line.ends_with(" 1")));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failing_meter_readings_do_not_stop_the_sync() {
+        use wiremock::matchers::{method, path_regex};
+        use wiremock::{Mock, ResponseTemplate};
+
+        let server = start_mock_server().await;
+        Mock::given(method("POST"))
+            .and(path_regex(r"^/api/meterdata/getmeterreadings/"))
+            .respond_with(ResponseTemplate::new(500))
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        let path = std::env::temp_dir().join(format!("eloverblik-readings-outage-test-{}", std::process::id()));
+        let syncer = test_syncer(&server, &path);
+
+        syncer.run().await.unwrap();
+
+        let mark = syncer.high_water_mark(METERING_POINT_ID, Utc::now().date_naive()).unwrap();
+        assert_eq!(mark, Some("2023-08-01T23:00:00Z".parse().unwrap()));
+        assert!(syncer.stores[0].get(StoreKind::MeterReadings, METERING_POINT_ID).unwrap().is_none());
+        assert!(syncer.metrics.encode().unwrap().contains("eloverblik_skipped_fetches_total{kind=\"meter_readings\"} 1"));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failing_details_do_not_stop_the_sync() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, ResponseTemplate};
+
+        let server = start_mock_server().await;
+        let path = std::env::temp_dir().join(format!("eloverblik-details-outage-test-{}", std::process::id()));
+        let syncer = test_syncer(&server, &path);
+        syncer.run().await.unwrap();
+
+        Mock::given(method("POST"))
+            .and(wiremock::matchers::path("/api/meteringpoints/meteringpoint/getdetails"))
+            .respond_with(ResponseTemplate::new(500))
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        syncer.run().await.unwrap();
+
+        let encoded = syncer.metrics.encode().unwrap();
+        assert!(encoded.contains("eloverblik_skipped_fetches_total{kind=\"details\"} 1"));
+        assert!(encoded.lines().any(|line| line.starts_with("eloverblik_metering_point_info{") && line.contains("grid_operator=\"Test Net A/S\"")));
+        assert!(encoded.contains("eloverblik_syncs_total 2"));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn import_stores_csv_export() {
+        let server = start_mock_server().await;
+        let path = std::env::temp_dir().join(format!("eloverblik-import-test-{}", std::process::id()));
+        let syncer = test_syncer(&server, &path);
+
+        let document = eloverblik_client::export::parse_timeseries_csv(TIMESERIES_EXPORT_FIXTURE).unwrap();
+        syncer.import(document).await.unwrap();
+
+        let hourly = syncer.get_usage_timeseries(&format!("{}_hourly", METERING_POINT_ID), Granularity::Hourly).unwrap();
+        assert_eq!(hourly.data["08/02/2023 00:00"].wh, 0.5);
+        assert_eq!(hourly.data["08/02/2023 00:00"].spot_price, 0.1);
+        let mark = syncer.high_water_mark(METERING_POINT_ID, Utc::now().date_naive()).unwrap();
+        assert_eq!(mark, Some("2023-08-01T23:00:00Z".parse().unwrap()));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn import_keeps_hours_of_partial_days() {
+        let server = start_mock_server().await;
+        let path = std::env::temp_dir().join(format!("eloverblik-import-partial-test-{}", std::process::id()));
+        let syncer = test_syncer(&server, &path);
+
+        // Starts an hour into the day and skips the hour after
+        let csv = format!("Målepunkt id;Fra dato;Til dato;Mængde;Måleenhed;Kvalitet\n\
+            {id};02-08-2023 01:00:00;02-08-2023 02:00:00;0,25;KWH;Målt\n\
+            {id};02-08-2023 03:00:00;02-08-2023 04:00:00;2;KWH;Målt\n", id = METERING_POINT_ID);
+        syncer.import(eloverblik_client::export::parse_timeseries_csv(&csv).unwrap()).await.unwrap();
+
+        let hourly = syncer.get_usage_timeseries(&format!("{}_hourly", METERING_POINT_ID), Granularity::Hourly).unwrap();
+        let keys : Vec<&str> = hourly.data.keys().map(|key| key.as_str()).collect();
+        assert_eq!(keys, vec!["08/02/2023 01:00", "08/02/2023 03:00"]);
+        assert_eq!(hourly.data["08/02/2023 01:00"].wh, 0.25);
+        assert_eq!(hourly.data["08/02/2023 01:00"].spot_price, 0.05);
+        let mark = syncer.high_water_mark(METERING_POINT_ID, Utc::now().date_naive()).unwrap();
+        assert_eq!(mark, Some("2023-08-02T01:00:00Z".parse().unwrap()));
+
+        let quarterly = format!("Målepunkt id;Fra dato;Til dato;Mængde;Måleenhed;Kvalitet\n\
+            {};02-08-2023 00:00:00;02-08-2023 00:15:00;0,1;KWH;Målt\n", METERING_POINT_ID);
+        let err = syncer.import(eloverblik_client::export::parse_timeseries_csv(&quarterly).unwrap()).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidImport(_)));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}